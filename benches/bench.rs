@@ -18,12 +18,14 @@ fn get_msg_elem_type() -> Message {
                 tag: 1,
                 kind: Kind::Int32,
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "s".to_string(),
                 tag: 2,
                 kind: Kind::String,
                 repeated: false,
+                required: false,
             },
         ],
         true,
@@ -39,12 +41,14 @@ fn get_msg_foo_embed_type() -> Message {
                 tag: 1,
                 kind: Kind::Int32,
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "b".to_string(),
                 tag: 2,
                 kind: Kind::String,
                 repeated: false,
+                required: false,
             },
         ],
         true,
@@ -60,42 +64,49 @@ fn get_msg_foo_type() -> Message {
                 tag: 1,
                 kind: Kind::String,
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "b".to_string(),
                 tag: 2,
                 kind: Kind::Bool,
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "c".to_string(),
                 tag: 3,
                 kind: Kind::Int32,
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "d".to_string(),
                 tag: 4,
                 kind: Kind::Message(Rc::new(get_msg_foo_embed_type())),
                 repeated: false,
+                required: false,
             },
             Field {
                 name: "e".to_string(),
                 tag: 5,
                 kind: Kind::Int32,
                 repeated: true,
+                required: false,
             },
             Field {
                 name: "f".to_string(),
                 tag: 6,
                 kind: Kind::String,
                 repeated: true,
+                required: false,
             },
             Field {
                 name: "g".to_string(),
                 tag: 7,
                 kind: Kind::Message(Rc::new(get_msg_elem_type())),
                 repeated: true,
+                required: false,
             },
         ],
         true,
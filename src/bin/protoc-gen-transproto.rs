@@ -0,0 +1,478 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+use transproto::descriptor::DescriptorPool;
+use transproto::metadata::*;
+use transproto::proto::*;
+use transproto::{Error, Result};
+
+// https://github.com/protocolbuffers/protobuf/blob/main/src/google/protobuf/compiler/plugin.proto
+
+const FEATURE_PROTO3_OPTIONAL: u64 = 1;
+
+struct Request<'a> {
+    files_to_generate: Vec<String>,
+    parameter: String,
+    proto_files: Vec<&'a [u8]>,
+}
+
+fn read_string(data: &[u8]) -> Result<String> {
+    String::from_utf8(data.to_vec()).map_err(|e| Error::Wrap(e.into()))
+}
+
+fn decode_request(data: &[u8]) -> Result<Request<'_>> {
+    let mut req = Request {
+        files_to_generate: Vec::new(),
+        parameter: String::new(),
+        proto_files: Vec::new(),
+    };
+    let mut dec = Decoder::new(data);
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match wire {
+            WIRE_VARINT => dec.read_varint().map(|_| ()).map_err(Error::from)?,
+            WIRE_32BIT => dec.read_32bit().map(|_| ()).map_err(Error::from)?,
+            WIRE_64BIT => dec.read_64bit().map(|_| ()).map_err(Error::from)?,
+            WIRE_LEN_DELIM => {
+                let s = dec.read_data().map_err(Error::from)?;
+                match tag {
+                    1 => req.files_to_generate.push(read_string(s)?),
+                    2 => req.parameter = read_string(s)?,
+                    15 => req.proto_files.push(s),
+                    _ => {}
+                }
+            }
+            _ => return Err(Error::InvalidWireType),
+        }
+    }
+    Ok(req)
+}
+
+fn encode_response(result: Result<Vec<(String, Vec<u8>)>>) -> Vec<u8> {
+    let mut enc = Encoder::new();
+    match result {
+        Ok(files) => {
+            enc.emit_varint(2, FEATURE_PROTO3_OPTIONAL);
+            let mut file = Encoder::new();
+            for (name, content) in files {
+                file.clear();
+                file.emit_len_delim(1, name.as_bytes());
+                file.emit_len_delim(15, &content);
+                enc.emit_len_delim(15, file.as_bytes());
+            }
+        }
+        Err(e) => enc.emit_len_delim(1, e.to_string().as_bytes()),
+    }
+    enc.into_inner()
+}
+
+fn fn_name(full_name: &str) -> String {
+    let chars = full_name.chars().collect::<Vec<_>>();
+    let mut z = String::with_capacity(full_name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '.' {
+            z.push('_');
+        } else if c.is_ascii_uppercase() {
+            let prev = if i > 0 { chars[i - 1] } else { '.' };
+            let next = chars.get(i + 1).copied().unwrap_or('.');
+            if prev.is_ascii_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_ascii_uppercase() && next.is_ascii_lowercase())
+            {
+                z.push('_');
+            }
+            z.push(c.to_ascii_lowercase());
+        } else {
+            z.push(c);
+        }
+    }
+    z
+}
+
+fn kind_expr(kind: &Kind) -> String {
+    match kind {
+        Kind::Double => "Kind::Double".to_string(),
+        Kind::Float => "Kind::Float".to_string(),
+        Kind::Int32 => "Kind::Int32".to_string(),
+        Kind::Int64 => "Kind::Int64".to_string(),
+        Kind::Uint32 => "Kind::Uint32".to_string(),
+        Kind::Uint64 => "Kind::Uint64".to_string(),
+        Kind::Sint32 => "Kind::Sint32".to_string(),
+        Kind::Sint64 => "Kind::Sint64".to_string(),
+        Kind::Fixed32 => "Kind::Fixed32".to_string(),
+        Kind::Fixed64 => "Kind::Fixed64".to_string(),
+        Kind::Sfixed32 => "Kind::Sfixed32".to_string(),
+        Kind::Sfixed64 => "Kind::Sfixed64".to_string(),
+        Kind::Bool => "Kind::Bool".to_string(),
+        Kind::String => "Kind::String".to_string(),
+        Kind::Bytes => "Kind::Bytes".to_string(),
+        Kind::Message(msg) => format!("Kind::Message(Rc::new({}()))", fn_name(msg.get_name())),
+        Kind::Map(entry) => format!("Kind::Map(Rc::new({}()))", fn_name(entry.get_name())),
+    }
+}
+
+struct RustGen {
+    out: String,
+    done: HashSet<String>,
+}
+
+impl RustGen {
+    fn message(&mut self, msg: &Message) {
+        if !self.done.insert(msg.get_name().to_string()) {
+            return;
+        }
+        let out = &mut self.out;
+        let _ = writeln!(out);
+        let _ = writeln!(out, "pub fn {}() -> Message {{", fn_name(msg.get_name()));
+        let _ = writeln!(out, "    Message::new(");
+        let _ = writeln!(out, "        {:?}.to_string(),", msg.get_name());
+        let _ = writeln!(out, "        vec![");
+        for f in msg.get_fields() {
+            let _ = writeln!(out, "            Field {{");
+            let _ = writeln!(out, "                name: {:?}.to_string(),", f.name);
+            let _ = writeln!(out, "                tag: {},", f.tag);
+            let _ = writeln!(out, "                kind: {},", kind_expr(&f.kind));
+            let _ = writeln!(out, "                repeated: {},", f.repeated);
+            let _ = writeln!(out, "                required: {},", f.required);
+            let _ = writeln!(out, "            }},");
+        }
+        let _ = writeln!(out, "        ],");
+        let _ = writeln!(out, "        true,");
+        let _ = writeln!(out, "    )");
+        if !msg.get_reserved().is_empty() {
+            let ranges = msg
+                .get_reserved()
+                .iter()
+                .map(|r| format!("{:?}", r))
+                .collect::<Vec<_>>();
+            let _ = writeln!(out, "    .with_reserved(vec![{}])", ranges.join(", "));
+        }
+        let _ = writeln!(out, "}}");
+        for f in msg.get_fields() {
+            if let Kind::Message(ref m) | Kind::Map(ref m) = f.kind {
+                self.message(m);
+            }
+        }
+    }
+
+    fn service(&mut self, svc: &Service) {
+        let out = &mut self.out;
+        let _ = writeln!(out);
+        let _ = writeln!(out, "pub fn {}_service() -> Service {{", fn_name(&svc.name));
+        let _ = writeln!(out, "    Service {{");
+        let _ = writeln!(out, "        name: {:?}.to_string(),", svc.name);
+        let _ = writeln!(out, "        methods: vec![");
+        for m in svc.methods.iter() {
+            let _ = writeln!(out, "            Method {{");
+            let _ = writeln!(out, "                name: {:?}.to_string(),", m.name);
+            let _ = writeln!(
+                out,
+                "                input: Rc::new({}()),",
+                fn_name(m.input.get_name())
+            );
+            let _ = writeln!(
+                out,
+                "                output: Rc::new({}()),",
+                fn_name(m.output.get_name())
+            );
+            let _ = writeln!(out, "            }},");
+        }
+        let _ = writeln!(out, "        ],");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}");
+        for m in svc.methods.iter() {
+            self.message(&m.input);
+            self.message(&m.output);
+        }
+    }
+}
+
+fn gen_rust(pool: &DescriptorPool, file: &str) -> Vec<u8> {
+    let mut gen = RustGen {
+        out: String::new(),
+        done: HashSet::new(),
+    };
+    let _ = writeln!(
+        gen.out,
+        "// Code generated by protoc-gen-transproto. DO NOT EDIT."
+    );
+    let _ = writeln!(gen.out, "// source: {}", file);
+    let _ = writeln!(gen.out);
+    let _ = writeln!(gen.out, "use std::rc::Rc;");
+    let _ = writeln!(gen.out);
+    let _ = writeln!(gen.out, "use transproto::metadata::*;");
+    for svc in pool.get_file_services(file) {
+        gen.service(svc);
+    }
+    for msg in pool.get_file_messages(file) {
+        gen.message(msg);
+    }
+    gen.out.into_bytes()
+}
+
+fn file_imports(data: &[u8]) -> Result<(String, Vec<String>)> {
+    let mut name = String::new();
+    let mut imports = Vec::new();
+    let mut dec = Decoder::new(data);
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match wire {
+            WIRE_VARINT => dec.read_varint().map(|_| ()).map_err(Error::from)?,
+            WIRE_32BIT => dec.read_32bit().map(|_| ()).map_err(Error::from)?,
+            WIRE_64BIT => dec.read_64bit().map(|_| ()).map_err(Error::from)?,
+            WIRE_LEN_DELIM => {
+                let s = dec.read_data().map_err(Error::from)?;
+                match tag {
+                    1 => name = read_string(s)?,
+                    3 => imports.push(read_string(s)?),
+                    _ => {}
+                }
+            }
+            _ => return Err(Error::InvalidWireType),
+        }
+    }
+    Ok((name, imports))
+}
+
+// The file and its transitive imports. protoc lists every file after its imports, so walking
+// backwards reaches each import after the files that need it.
+fn gen_descriptor_set(req: &Request, file: &str) -> Result<Vec<u8>> {
+    let mut protos = Vec::with_capacity(req.proto_files.len());
+    for &data in req.proto_files.iter() {
+        protos.push((file_imports(data)?, data));
+    }
+    let mut wanted = HashSet::from([file]);
+    let mut included = Vec::new();
+    for ((name, imports), data) in protos.iter().rev() {
+        if wanted.contains(name.as_str()) {
+            wanted.extend(imports.iter().map(String::as_str));
+            included.push(*data);
+        }
+    }
+    let mut enc = Encoder::new();
+    for data in included.iter().rev() {
+        enc.emit_len_delim(1, data);
+    }
+    Ok(enc.into_inner())
+}
+
+fn generate(req: &Request) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut rust = false;
+    let mut descriptor_set = false;
+    for opt in req.parameter.split(',').filter(|s| !s.is_empty()) {
+        match opt {
+            "rust" => rust = true,
+            "descriptor_set" => descriptor_set = true,
+            _ => return Err(Error::Wrap(format!("unknown parameter: {}", opt).into())),
+        }
+    }
+    if !rust {
+        descriptor_set = true;
+    }
+
+    let pool =
+        DescriptorPool::from_files_for(req.proto_files.iter().copied(), &req.files_to_generate)?;
+    let mut files = Vec::new();
+    for file in req.files_to_generate.iter() {
+        let base = file.strip_suffix(".proto").unwrap_or(file);
+        if descriptor_set {
+            files.push((
+                format!("{}.transproto.pb", base),
+                gen_descriptor_set(req, file)?,
+            ));
+        }
+        if rust {
+            files.push((format!("{}.transproto.rs", base), gen_rust(&pool, file)));
+        }
+    }
+    Ok(files)
+}
+
+fn run(input: &[u8]) -> Result<Vec<u8>> {
+    let req = decode_request(input)?;
+    Ok(encode_response(generate(&req)))
+}
+
+fn main() {
+    let mut input = Vec::new();
+    let r = io::stdin()
+        .read_to_end(&mut input)
+        .map_err(Error::from)
+        .and_then(|_| run(&input))
+        .and_then(|out| io::stdout().write_all(&out).map_err(Error::from));
+    if let Err(e) = r {
+        eprintln!("protoc-gen-transproto: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+#[path = "../descriptor/fixtures.rs"]
+mod fixtures;
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+
+    // foo.proto:
+    //
+    //   syntax = "proto2";
+    //   package pkg;
+    //   import "dep.proto";  // package dep; message Node { optional Node next = 1; }
+    //   message Bar { required string s = 1; reserved 2 to 4; }
+    //   message Foo { optional int32 a = 1; repeated Bar b = 2; map<string, Bar> m = 3; }
+    //   service Svc { rpc Get(Foo) returns (Bar); }
+    //
+    // other.proto, sent along but not imported: package other; message Baz { optional string s = 1; }
+    fn captured_request(parameter: &str) -> Vec<u8> {
+        let mut bar = Encoder::from_vec(message("Bar", &[field("s", 1, 2, 9, "")]));
+        let mut range = Encoder::new();
+        range.emit_varint(1, 2);
+        range.emit_varint(2, 5);
+        bar.emit_len_delim(9, range.as_bytes());
+
+        let mut entry = Encoder::from_vec(message(
+            "MEntry",
+            &[
+                field("key", 1, 1, 9, ""),
+                field("value", 2, 1, 11, ".pkg.Bar"),
+            ],
+        ));
+        let mut opts = Encoder::new();
+        opts.emit_varint(7, 1);
+        entry.emit_len_delim(7, opts.as_bytes());
+
+        let mut foo = Encoder::from_vec(message(
+            "Foo",
+            &[
+                field("a", 1, 1, 5, ""),
+                field("b", 2, 3, 11, ".pkg.Bar"),
+                field("m", 3, 3, 11, ".pkg.Foo.MEntry"),
+            ],
+        ));
+        foo.emit_len_delim(3, entry.as_bytes());
+
+        let mut method = Encoder::new();
+        method.emit_len_delim(1, b"Get");
+        method.emit_len_delim(2, b".pkg.Foo");
+        method.emit_len_delim(3, b".pkg.Bar");
+        let mut svc = Encoder::new();
+        svc.emit_len_delim(1, b"Svc");
+        svc.emit_len_delim(2, method.as_bytes());
+
+        let dep = file(
+            "dep.proto",
+            "dep",
+            &[message("Node", &[field("next", 1, 1, 11, ".dep.Node")])],
+        );
+
+        let mut foo_file = Encoder::from_vec(file(
+            "foo.proto",
+            "pkg",
+            &[bar.into_inner(), foo.into_inner()],
+        ));
+        foo_file.emit_len_delim(3, b"dep.proto");
+        foo_file.emit_len_delim(6, svc.as_bytes());
+        foo_file.emit_len_delim(12, b"proto2");
+
+        let other = file(
+            "other.proto",
+            "other",
+            &[message("Baz", &[field("s", 1, 1, 9, "")])],
+        );
+
+        let mut req = Encoder::new();
+        req.emit_len_delim(1, b"foo.proto");
+        req.emit_len_delim(2, parameter.as_bytes());
+        req.emit_len_delim(15, &dep);
+        req.emit_len_delim(15, foo_file.as_bytes());
+        req.emit_len_delim(15, &other);
+        req.into_inner()
+    }
+
+    fn decode_response(data: &[u8]) -> (Option<String>, Vec<(String, Vec<u8>)>) {
+        let mut error = None;
+        let mut files = Vec::new();
+        let mut dec = Decoder::new(data);
+        while !dec.eof() {
+            let (tag, wire) = dec.read_key().unwrap();
+            if wire == WIRE_VARINT {
+                dec.read_varint().unwrap();
+                continue;
+            }
+            let s = dec.read_data().unwrap();
+            match tag {
+                1 => error = Some(read_string(s).unwrap()),
+                15 => {
+                    let mut name = String::new();
+                    let mut content = Vec::new();
+                    let mut dec = Decoder::new(s);
+                    while !dec.eof() {
+                        let (tag, _) = dec.read_key().unwrap();
+                        let s = dec.read_data().unwrap();
+                        match tag {
+                            1 => name = read_string(s).unwrap(),
+                            15 => content = s.to_vec(),
+                            _ => {}
+                        }
+                    }
+                    files.push((name, content));
+                }
+                _ => {}
+            }
+        }
+        (error, files)
+    }
+
+    #[test]
+    fn test_protoc_gen_descriptor_set() {
+        let out = run(&captured_request("")).unwrap();
+        let (error, files) = decode_response(&out);
+        assert!(error.is_none());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "foo.transproto.pb");
+
+        let pool = DescriptorPool::decode_file_descriptor_set(&files[0].1).unwrap();
+        let foo = pool.get_message("pkg.Foo").unwrap();
+        assert!(matches!(foo.get_by_name("m").unwrap().kind, Kind::Map(_)));
+        assert!(foo.get_by_name("b").unwrap().repeated);
+        assert!(pool.get_message("pkg.Bar").unwrap().has_required());
+        assert!(pool.get_message("dep.Node").is_none());
+        assert!(pool.get_message("other.Baz").is_none());
+
+        let mut names = Vec::new();
+        let mut dec = Decoder::new(&files[0].1);
+        while !dec.eof() {
+            dec.read_key().unwrap();
+            names.push(file_imports(dec.read_data().unwrap()).unwrap().0);
+        }
+        assert_eq!(names, ["dep.proto", "foo.proto"]);
+    }
+
+    #[test]
+    fn test_protoc_gen_rust() {
+        let out = run(&captured_request("rust")).unwrap();
+        let (error, files) = decode_response(&out);
+        assert!(error.is_none());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "foo.transproto.rs");
+        let code = String::from_utf8(files[0].1.clone()).unwrap();
+        assert!(code.contains("pub fn pkg_svc_service() -> Service {"));
+        assert!(code.contains("pub fn pkg_foo_m_entry() -> Message {"));
+        assert!(code.contains("kind: Kind::Map(Rc::new(pkg_foo_m_entry())),"));
+        assert!(code.contains("kind: Kind::Message(Rc::new(pkg_bar())),"));
+        assert_eq!(code.matches("pub fn pkg_bar() -> Message {").count(), 1);
+        assert!(code.contains("    .with_reserved(vec![2..5])\n"));
+        assert!(!code.contains("dep_node"));
+    }
+
+    #[test]
+    fn test_protoc_gen_bad_parameter() {
+        let out = run(&captured_request("yaml")).unwrap();
+        let (error, files) = decode_response(&out);
+        assert_eq!(error.as_deref(), Some("wrap: unknown parameter: yaml"));
+        assert!(files.is_empty());
+    }
+}
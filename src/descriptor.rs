@@ -0,0 +1,613 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::metadata::*;
+use crate::proto::*;
+use crate::{Error, Result, TypeResolver};
+
+// https://github.com/protocolbuffers/protobuf/blob/main/src/google/protobuf/descriptor.proto
+
+const LABEL_REQUIRED: u64 = 2;
+const LABEL_REPEATED: u64 = 3;
+
+fn error(msg: String) -> Error {
+    Error::Wrap(msg.into())
+}
+
+fn read_fields<'a, F>(data: &'a [u8], mut f: F) -> Result<()>
+where
+    F: FnMut(u32, u64, &'a [u8]) -> Result<()>,
+{
+    let mut dec = Decoder::new(data);
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match wire {
+            WIRE_VARINT => f(tag, dec.read_varint().map_err(Error::from)?, &[])?,
+            WIRE_LEN_DELIM => f(tag, 0, dec.read_data().map_err(Error::from)?)?,
+            WIRE_32BIT => dec.read_32bit().map(|_| ()).map_err(Error::from)?,
+            WIRE_64BIT => dec.read_64bit().map(|_| ()).map_err(Error::from)?,
+            _ => return Err(Error::InvalidWireType),
+        }
+    }
+    Ok(())
+}
+
+fn read_string(data: &[u8]) -> Result<String> {
+    String::from_utf8(data.to_vec()).map_err(|e| Error::Wrap(e.into()))
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+#[derive(Default)]
+struct FieldProto {
+    name: String,
+    number: u32,
+    label: u64,
+    ty: u64,
+    type_name: String,
+}
+
+struct MessageProto {
+    file: String,
+    fields: Vec<FieldProto>,
+    reserved: Vec<Range<u32>>,
+    map_entry: bool,
+}
+
+struct MethodProto {
+    name: String,
+    input: String,
+    output: String,
+}
+
+struct ServiceProto {
+    file: String,
+    name: String,
+    methods: Vec<MethodProto>,
+}
+
+#[derive(Default)]
+struct Loader {
+    messages: HashMap<String, MessageProto>,
+    message_order: Vec<String>,
+    enums: Vec<Enum>,
+    services: Vec<ServiceProto>,
+}
+
+impl Loader {
+    fn add_field(&mut self, data: &[u8]) -> Result<FieldProto> {
+        let mut field = FieldProto::default();
+        read_fields(data, |tag, v, s| {
+            match tag {
+                1 => field.name = read_string(s)?,
+                3 => field.number = v as u32,
+                4 => field.label = v,
+                5 => field.ty = v,
+                6 => field.type_name = read_string(s)?.trim_start_matches('.').to_string(),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(field)
+    }
+
+    fn add_enum(&mut self, scope: &str, data: &[u8]) -> Result<()> {
+        let mut e = Enum {
+            name: String::new(),
+            values: Vec::new(),
+            reserved: Vec::new(),
+        };
+        read_fields(data, |tag, _, s| {
+            match tag {
+                1 => e.name = qualify(scope, &read_string(s)?),
+                2 => {
+                    let mut value = EnumValue {
+                        name: String::new(),
+                        number: 0,
+                    };
+                    read_fields(s, |tag, v, s| {
+                        match tag {
+                            1 => value.name = read_string(s)?,
+                            2 => value.number = v as i32,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    e.values.push(value);
+                }
+                4 => {
+                    let (mut start, mut end) = (0, 0);
+                    read_fields(s, |tag, v, _| {
+                        match tag {
+                            1 => start = v as i32,
+                            2 => end = v as i32,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    e.reserved.push(start..=end);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        self.enums.push(e);
+        Ok(())
+    }
+
+    fn add_message(&mut self, file: &str, scope: &str, data: &[u8]) -> Result<()> {
+        let mut name = String::new();
+        read_fields(data, |tag, _, s| {
+            if tag == 1 {
+                name = read_string(s)?;
+            }
+            Ok(())
+        })?;
+        let full_name = qualify(scope, &name);
+        let mut msg = MessageProto {
+            file: file.to_string(),
+            fields: Vec::new(),
+            reserved: Vec::new(),
+            map_entry: false,
+        };
+        read_fields(data, |tag, _, s| {
+            match tag {
+                2 => msg.fields.push(self.add_field(s)?),
+                3 => self.add_message(file, &full_name, s)?,
+                4 => self.add_enum(&full_name, s)?,
+                7 => read_fields(s, |tag, v, _| {
+                    if tag == 7 {
+                        msg.map_entry = v != 0;
+                    }
+                    Ok(())
+                })?,
+                9 => {
+                    let mut range = 0..0;
+                    read_fields(s, |tag, v, _| {
+                        match tag {
+                            1 => range.start = v as u32,
+                            2 => range.end = v as u32,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    msg.reserved.push(range);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        self.message_order.push(full_name.clone());
+        self.messages.insert(full_name, msg);
+        Ok(())
+    }
+
+    fn add_service(&mut self, file: &str, scope: &str, data: &[u8]) -> Result<()> {
+        let mut svc = ServiceProto {
+            file: file.to_string(),
+            name: String::new(),
+            methods: Vec::new(),
+        };
+        read_fields(data, |tag, _, s| {
+            match tag {
+                1 => svc.name = qualify(scope, &read_string(s)?),
+                2 => {
+                    let mut method = MethodProto {
+                        name: String::new(),
+                        input: String::new(),
+                        output: String::new(),
+                    };
+                    read_fields(s, |tag, _, s| {
+                        match tag {
+                            1 => method.name = read_string(s)?,
+                            2 => method.input = read_string(s)?.trim_start_matches('.').to_string(),
+                            3 => {
+                                method.output = read_string(s)?.trim_start_matches('.').to_string()
+                            }
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    svc.methods.push(method);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        self.services.push(svc);
+        Ok(())
+    }
+
+    fn add_file(&mut self, data: &[u8]) -> Result<()> {
+        let mut name = String::new();
+        let mut package = String::new();
+        read_fields(data, |tag, _, s| {
+            match tag {
+                1 => name = read_string(s)?,
+                2 => package = read_string(s)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        read_fields(data, |tag, _, s| match tag {
+            4 => self.add_message(&name, &package, s),
+            5 => self.add_enum(&package, s),
+            6 => self.add_service(&name, &package, s),
+            _ => Ok(()),
+        })
+    }
+}
+
+impl Loader {
+    // Messages that reach a cycle of message fields, or a field of an unsupported type such as a
+    // group, cannot be built.
+    fn unbuildable_messages(&self) -> HashSet<&str> {
+        fn visit<'a>(
+            loader: &'a Loader,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            memo: &mut HashMap<&'a str, bool>,
+        ) -> bool {
+            if let Some(&r) = memo.get(name) {
+                return r;
+            }
+            if path.contains(&name) {
+                return true;
+            }
+            path.push(name);
+            let r = loader.messages.get(name).is_some_and(|m| {
+                m.fields.iter().any(|f| match f.ty {
+                    11 => visit(loader, &f.type_name, path, memo),
+                    ty => !matches!(ty, 1..=9 | 12..=18),
+                })
+            });
+            path.pop();
+            memo.insert(name, r);
+            r
+        }
+        let mut memo = HashMap::new();
+        for name in self.message_order.iter() {
+            visit(self, name, &mut Vec::new(), &mut memo);
+        }
+        memo.into_iter().filter(|x| x.1).map(|x| x.0).collect()
+    }
+}
+
+struct Builder<'a> {
+    loader: &'a Loader,
+    built: HashMap<String, Rc<Message>>,
+    building: Vec<String>,
+}
+
+impl Builder<'_> {
+    fn field_kind(&mut self, owner: &str, field: &FieldProto) -> Result<Kind> {
+        Ok(match field.ty {
+            1 => Kind::Double,
+            2 => Kind::Float,
+            3 => Kind::Int64,
+            4 => Kind::Uint64,
+            5 => Kind::Int32,
+            6 => Kind::Fixed64,
+            7 => Kind::Fixed32,
+            8 => Kind::Bool,
+            9 => Kind::String,
+            11 => {
+                let msg = self.build(&field.type_name)?;
+                if self.loader.messages[&field.type_name].map_entry {
+                    Kind::Map(msg)
+                } else {
+                    Kind::Message(msg)
+                }
+            }
+            12 => Kind::Bytes,
+            13 => Kind::Uint32,
+            14 => Kind::Int32,
+            15 => Kind::Sfixed32,
+            16 => Kind::Sfixed64,
+            17 => Kind::Sint32,
+            18 => Kind::Sint64,
+            _ => {
+                return Err(error(format!(
+                    "unsupported field type: {}.{}",
+                    owner, field.name
+                )))
+            }
+        })
+    }
+
+    fn build(&mut self, name: &str) -> Result<Rc<Message>> {
+        if let Some(msg) = self.built.get(name) {
+            return Ok(msg.clone());
+        }
+        let proto = self
+            .loader
+            .messages
+            .get(name)
+            .ok_or_else(|| error(format!("unknown message: {}", name)))?;
+        if self.building.iter().any(|x| x == name) {
+            return Err(error(format!(
+                "recursive message is not supported: {}",
+                name
+            )));
+        }
+        self.building.push(name.to_string());
+        let mut fields = Vec::with_capacity(proto.fields.len());
+        for f in proto.fields.iter() {
+            let kind = self.field_kind(name, f)?;
+            let is_map = matches!(kind, Kind::Map(_));
+            fields.push(Field {
+                name: f.name.clone(),
+                tag: f.number,
+                kind,
+                repeated: f.label == LABEL_REPEATED && !is_map,
+                required: f.label == LABEL_REQUIRED,
+            });
+        }
+        self.building.pop();
+        let msg = Rc::new(
+            Message::new(name.to_string(), fields, true).with_reserved(proto.reserved.clone()),
+        );
+        self.built.insert(name.to_string(), msg.clone());
+        Ok(msg)
+    }
+}
+
+pub struct DescriptorPool {
+    messages: HashMap<String, Rc<Message>>,
+    enums: Vec<Enum>,
+    files: Vec<(String, Vec<Rc<Message>>)>,
+    services: Vec<(String, Service)>,
+}
+
+impl DescriptorPool {
+    // Recursive messages, messages with fields of unsupported types, and the messages and
+    // services that reach them, are left out.
+    pub fn from_files<'a, I>(files: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        Self::load(files, None)
+    }
+
+    // Only builds the messages and services declared in `targets` and the types they
+    // reference, failing if any of them is recursive or has a field of an unsupported type.
+    pub fn from_files_for<'a, I>(files: I, targets: &[String]) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        Self::load(files, Some(targets))
+    }
+
+    fn load<'a, I>(files: I, targets: Option<&[String]>) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut loader = Loader::default();
+        for data in files {
+            loader.add_file(data)?;
+        }
+        let skip = match targets {
+            Some(_) => HashSet::new(),
+            None => loader.unbuildable_messages(),
+        };
+        let wanted = |file: &String| targets.is_none_or(|t| t.contains(file));
+        let mut builder = Builder {
+            loader: &loader,
+            built: HashMap::new(),
+            building: Vec::new(),
+        };
+        let mut files: Vec<(String, Vec<Rc<Message>>)> = Vec::new();
+        for name in loader.message_order.iter() {
+            let file = &loader.messages[name].file;
+            if skip.contains(name.as_str()) || !wanted(file) {
+                continue;
+            }
+            let msg = builder.build(name)?;
+            match files.iter_mut().find(|x| &x.0 == file) {
+                Some(x) => x.1.push(msg),
+                None => files.push((file.clone(), vec![msg])),
+            }
+        }
+        let mut services = Vec::with_capacity(loader.services.len());
+        for svc in loader.services.iter() {
+            if !wanted(&svc.file)
+                || svc
+                    .methods
+                    .iter()
+                    .any(|m| skip.contains(m.input.as_str()) || skip.contains(m.output.as_str()))
+            {
+                continue;
+            }
+            let mut methods = Vec::with_capacity(svc.methods.len());
+            for m in svc.methods.iter() {
+                methods.push(Method {
+                    name: m.name.clone(),
+                    input: builder.build(&m.input)?,
+                    output: builder.build(&m.output)?,
+                });
+            }
+            services.push((
+                svc.file.clone(),
+                Service {
+                    name: svc.name.clone(),
+                    methods,
+                },
+            ));
+        }
+        Ok(Self {
+            messages: builder.built,
+            enums: loader.enums,
+            files,
+            services,
+        })
+    }
+
+    pub fn decode_file_descriptor_set(data: &[u8]) -> Result<Self> {
+        let mut files = Vec::new();
+        read_fields(data, |tag, _, s| {
+            if tag == 1 {
+                files.push(s);
+            }
+            Ok(())
+        })?;
+        Self::from_files(files)
+    }
+
+    pub fn get_message(&self, name: &str) -> Option<Rc<Message>> {
+        self.messages.get(name).cloned()
+    }
+
+    pub fn get_enums(&self) -> &[Enum] {
+        self.enums.as_slice()
+    }
+
+    pub fn get_file_messages(&self, file: &str) -> &[Rc<Message>] {
+        self.files
+            .iter()
+            .find(|x| x.0 == file)
+            .map(|x| x.1.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn get_file_services<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a Service> {
+        self.services
+            .iter()
+            .filter(move |x| x.0 == file)
+            .map(|x| &x.1)
+    }
+}
+
+// The type name is whatever follows the last '/' of the type URL.
+impl TypeResolver for DescriptorPool {
+    fn resolve(&self, type_url: &str) -> Option<Rc<Message>> {
+        let name = type_url.rsplit_once('/')?.1;
+        self.get_message(name)
+    }
+}
+
+#[cfg(test)]
+mod fixtures;
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+
+    #[test]
+    fn test_descriptor_pool() {
+        let a = file(
+            "a.proto",
+            "pa",
+            &[message(
+                "A",
+                &[field("x", 1, 2, 17, ""), field("e", 2, 1, 14, ".pa.E")],
+            )],
+        );
+        let b = file(
+            "b.proto",
+            "pb",
+            &[message("B", &[field("a", 3, 3, 11, ".pa.A")])],
+        );
+        let mut set = Encoder::new();
+        set.emit_len_delim(1, &a);
+        set.emit_len_delim(1, &b);
+        let pool = DescriptorPool::decode_file_descriptor_set(set.as_bytes()).unwrap();
+
+        let msg = pool.get_message("pb.B").unwrap();
+        let f = msg.get_by_tag(3).unwrap();
+        assert!(f.repeated);
+        match f.kind {
+            Kind::Message(ref m) => {
+                assert_eq!(m.get_name(), "pa.A");
+                assert!(Rc::ptr_eq(m, &pool.get_message("pa.A").unwrap()));
+                let x = m.get_by_name("x").unwrap();
+                assert!(x.required && matches!(x.kind, Kind::Sint32));
+                assert!(matches!(m.get_by_name("e").unwrap().kind, Kind::Int32));
+            }
+            _ => panic!("expect message kind"),
+        }
+        assert_eq!(pool.get_file_messages("a.proto").len(), 1);
+        assert!(pool.resolve("type.googleapis.com/pa.A").is_some());
+        assert!(pool.resolve("pa.A").is_none());
+    }
+
+    #[test]
+    fn test_descriptor_pool_reserved_and_enums() {
+        let mut range = Encoder::new();
+        range.emit_varint(1, 2);
+        range.emit_varint(2, 5);
+        let mut msg = Encoder::from_vec(message("A", &[field("x", 1, 1, 5, "")]));
+        msg.emit_len_delim(9, range.as_bytes());
+
+        let mut value = Encoder::new();
+        value.emit_len_delim(1, b"E_UNSPECIFIED");
+        value.emit_varint(2, 0);
+        let mut e = Encoder::new();
+        e.emit_len_delim(1, b"E");
+        e.emit_len_delim(2, value.as_bytes());
+        e.emit_len_delim(4, range.as_bytes());
+
+        let mut data = Encoder::from_vec(file("a.proto", "pa", &[msg.into_inner()]));
+        data.emit_len_delim(5, e.as_bytes());
+        let pool = DescriptorPool::from_files([data.as_bytes()]).unwrap();
+
+        assert_eq!(pool.get_message("pa.A").unwrap().get_reserved()[0], 2..5);
+        let enums = pool.get_enums();
+        assert_eq!(enums.len(), 1);
+        assert_eq!(enums[0].name, "pa.E");
+        assert_eq!(enums[0].values[0].name, "E_UNSPECIFIED");
+        assert_eq!(enums[0].reserved, vec![2..=5]);
+    }
+
+    #[test]
+    fn test_descriptor_pool_errors() {
+        let missing = file(
+            "a.proto",
+            "",
+            &[message("A", &[field("b", 1, 1, 11, ".B")])],
+        );
+        assert!(DescriptorPool::from_files([missing.as_slice()]).is_err());
+        let recursive = file(
+            "a.proto",
+            "",
+            &[message("A", &[field("a", 1, 1, 11, ".A")])],
+        );
+        let pool = DescriptorPool::from_files([recursive.as_slice()]).unwrap();
+        assert!(pool.get_message("A").is_none());
+        let targets = ["a.proto".to_string()];
+        assert_eq!(
+            DescriptorPool::from_files_for([recursive.as_slice()], &targets)
+                .err()
+                .unwrap()
+                .to_string(),
+            "wrap: recursive message is not supported: A"
+        );
+        let group = file(
+            "a.proto",
+            "",
+            &[
+                message("A", &[field("g", 1, 1, 10, "")]),
+                message("B", &[field("a", 1, 1, 11, ".A")]),
+                message("C", &[field("c", 1, 1, 9, "")]),
+            ],
+        );
+        let pool = DescriptorPool::from_files([group.as_slice()]).unwrap();
+        assert!(pool.get_message("A").is_none());
+        assert!(pool.get_message("B").is_none());
+        assert!(pool.get_message("C").is_some());
+        assert_eq!(
+            DescriptorPool::from_files_for([group.as_slice()], &targets)
+                .err()
+                .unwrap()
+                .to_string(),
+            "wrap: unsupported field type: A.g"
+        );
+    }
+}
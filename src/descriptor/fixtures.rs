@@ -0,0 +1,35 @@
+// Encoders for descriptor protos, shared with the protoc plugin tests which include this file by
+// path.
+
+use super::*;
+
+pub fn field(name: &str, number: u64, label: u64, ty: u64, type_name: &str) -> Vec<u8> {
+    let mut enc = Encoder::new();
+    enc.emit_len_delim(1, name.as_bytes());
+    enc.emit_varint(3, number);
+    enc.emit_varint(4, label);
+    enc.emit_varint(5, ty);
+    if !type_name.is_empty() {
+        enc.emit_len_delim(6, type_name.as_bytes());
+    }
+    enc.into_inner()
+}
+
+pub fn message(name: &str, fields: &[Vec<u8>]) -> Vec<u8> {
+    let mut enc = Encoder::new();
+    enc.emit_len_delim(1, name.as_bytes());
+    for f in fields {
+        enc.emit_len_delim(2, f);
+    }
+    enc.into_inner()
+}
+
+pub fn file(name: &str, package: &str, messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut enc = Encoder::new();
+    enc.emit_len_delim(1, name.as_bytes());
+    enc.emit_len_delim(2, package.as_bytes());
+    for m in messages {
+        enc.emit_len_delim(4, m);
+    }
+    enc.into_inner()
+}
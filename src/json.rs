@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Token<'a> {
     Invalid(&'a [u8]),
     Null,
@@ -14,6 +14,7 @@ pub enum Token<'a> {
     ArrayClose,
 }
 
+#[derive(Clone)]
 pub struct Iter<'a> {
     s: &'a [u8],
     i: usize,
@@ -36,10 +37,7 @@ impl<'a> Iter<'a> {
 
     fn skip_whitespace(&mut self) {
         let mut i = self.i;
-        while let Some(c) = self.s.get(i) {
-            if !c.is_ascii_whitespace() {
-                break;
-            }
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.s.get(i) {
             i += 1;
         }
         self.i = i;
@@ -48,14 +46,30 @@ impl<'a> Iter<'a> {
     fn read_string(&mut self) -> Token<'a> {
         let b = self.i;
         let mut i = b + 1;
-        while i < self.s.len() {
-            if self.s[i] == b'"' && self.s[i - 1] != b'\\' {
-                self.i = i + 1;
-                return Token::String(&self.s[b..self.i]);
+        while let Some(&c) = self.s.get(i) {
+            match c {
+                b'"' => {
+                    self.i = i + 1;
+                    return Token::String(&self.s[b..self.i]);
+                }
+                b'\\' => match self.s.get(i + 1) {
+                    Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => i += 2,
+                    Some(b'u')
+                        if self
+                            .s
+                            .get(i + 2..i + 6)
+                            .is_some_and(|h| h.iter().all(u8::is_ascii_hexdigit)) =>
+                    {
+                        i += 6
+                    }
+                    _ => break,
+                },
+                // control characters must be escaped
+                0..=0x1f => break,
+                _ => i += 1,
             }
-            i += 1;
         }
-        Token::Invalid(&self.s[b..])
+        Token::Invalid(&self.s[b..(i + 1).min(self.s.len())])
     }
 
     fn read_number(&mut self) -> Token<'a> {
@@ -63,12 +77,16 @@ impl<'a> Iter<'a> {
         let mut i = b + 1;
         while let Some(c) = self.s.get(i) {
             match c {
-                b'0'..=b'9' | b'.' | b'-' | b'e' | b'E' => i += 1,
+                b'0'..=b'9' | b'.' | b'-' | b'+' | b'e' | b'E' => i += 1,
                 _ => break,
             }
         }
+        let s = &self.s[b..i];
+        if !is_number(s) {
+            return Token::Invalid(s);
+        }
         self.i = i;
-        Token::Number(&self.s[b..i])
+        Token::Number(s)
     }
 
     #[inline]
@@ -77,21 +95,21 @@ impl<'a> Iter<'a> {
         tok
     }
 
-    fn fixed(&mut self, expected: usize, tok: Token<'a>) -> Token<'a> {
-        if self.i + expected > self.s.len() {
-            Token::Invalid(&self.s[self.i..])
-        } else {
-            self.i += expected;
+    fn literal(&mut self, lit: &[u8], tok: Token<'a>) -> Token<'a> {
+        if self.s[self.i..].starts_with(lit) {
+            self.i += lit.len();
             tok
+        } else {
+            Token::Invalid(&self.s[self.i..(self.i + lit.len()).min(self.s.len())])
         }
     }
 
     fn next_token(&mut self) -> Option<Token<'a>> {
         self.skip_whitespace();
         self.s.get(self.i).map(|&c| match c {
-            b'n' => self.fixed(4, Token::Null),
-            b't' => self.fixed(4, Token::True),
-            b'f' => self.fixed(5, Token::False),
+            b'n' => self.literal(b"null", Token::Null),
+            b't' => self.literal(b"true", Token::True),
+            b'f' => self.literal(b"false", Token::False),
             b'{' => self.consume(Token::Object),
             b'}' => self.consume(Token::ObjectClose),
             b'[' => self.consume(Token::Array),
@@ -115,73 +133,506 @@ impl<'a> Iterator for Iter<'a> {
     type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Token<'a>> {
-        self.next_token()
+        let tok = self.next_token();
+        if let Some(Token::Invalid(_)) = tok {
+            // nothing after an invalid token can be trusted
+            self.i = self.s.len();
+        }
+        tok
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pretty {
+    pub indent: Indent,
+    pub space_after_colon: bool,
+}
+
+impl Default for Pretty {
+    fn default() -> Self {
+        Self {
+            indent: Indent::Spaces(2),
+            space_after_colon: true,
+        }
+    }
+}
+
+impl Pretty {
+    fn newline(&self, depth: usize, z: &mut Vec<u8>) {
+        z.push(b'\n');
+        match self.indent {
+            Indent::Spaces(n) => z.resize(z.len() + n * depth, b' '),
+            Indent::Tab => z.resize(z.len() + depth, b'\t'),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Value,
+    Key,
+    Colon,
+    CommaOrClose,
+}
+
+// Reformats `s` one member or element per line. Empty objects and arrays stay
+// on one line. `s` must be a single well-formed JSON value.
+pub fn write_pretty(s: &[u8], z: &mut Vec<u8>, pretty: &Pretty) -> Result<(), String> {
+    // open brackets, innermost last
+    let mut stack = Vec::new();
+    let mut expect = Expect::Value;
+    let mut it = Iter::new(s).peekable();
+    while let Some(tok) = it.next() {
+        let unexpected = || format!("unexpected token: {:?}", tok);
+        match tok {
+            Token::Object | Token::Array => {
+                if expect != Expect::Value {
+                    return Err(unexpected());
+                }
+                let (open, close) = if tok == Token::Object {
+                    (b'{', Token::ObjectClose)
+                } else {
+                    (b'[', Token::ArrayClose)
+                };
+                z.push(open);
+                if it.peek() == Some(&close) {
+                    z.push(if open == b'{' { b'}' } else { b']' });
+                    it.next();
+                    expect = Expect::CommaOrClose;
+                } else {
+                    stack.push(open);
+                    pretty.newline(stack.len(), z);
+                    expect = if open == b'{' {
+                        Expect::Key
+                    } else {
+                        Expect::Value
+                    };
+                }
+            }
+            Token::ObjectClose | Token::ArrayClose => {
+                let open = if tok == Token::ObjectClose {
+                    b'{'
+                } else {
+                    b'['
+                };
+                if expect != Expect::CommaOrClose || stack.pop() != Some(open) {
+                    return Err(unexpected());
+                }
+                pretty.newline(stack.len(), z);
+                z.push(if open == b'{' { b'}' } else { b']' });
+            }
+            Token::Comma => {
+                if expect != Expect::CommaOrClose || stack.is_empty() {
+                    return Err(unexpected());
+                }
+                z.push(b',');
+                pretty.newline(stack.len(), z);
+                expect = if stack.last() == Some(&b'{') {
+                    Expect::Key
+                } else {
+                    Expect::Value
+                };
+            }
+            Token::Colon => {
+                if expect != Expect::Colon {
+                    return Err(unexpected());
+                }
+                z.push(b':');
+                if pretty.space_after_colon {
+                    z.push(b' ');
+                }
+                expect = Expect::Value;
+            }
+            Token::String(s) if expect == Expect::Key => {
+                z.extend_from_slice(s);
+                expect = Expect::Colon;
+            }
+            Token::Null | Token::True | Token::False | Token::Number(_) | Token::String(_) => {
+                if expect != Expect::Value {
+                    return Err(unexpected());
+                }
+                match tok {
+                    Token::Null => z.extend_from_slice(b"null"),
+                    Token::True => z.extend_from_slice(b"true"),
+                    Token::False => z.extend_from_slice(b"false"),
+                    Token::Number(s) | Token::String(s) => z.extend_from_slice(s),
+                    _ => unreachable!(),
+                }
+                expect = Expect::CommaOrClose;
+            }
+            Token::Invalid(_) => return Err(unexpected()),
+        }
+        // a complete top-level value takes nothing else
+        if stack.is_empty() && expect == Expect::CommaOrClose && it.peek().is_some() {
+            return Err(format!("unexpected token: {:?}", it.peek().unwrap()));
+        }
+    }
+    if !stack.is_empty() || expect != Expect::CommaOrClose {
+        return Err("unexpected eof".to_string());
+    }
+    Ok(())
+}
+
+// Numbers are written the way ECMAScript's Number.prototype.toString does.
+pub fn write_canonical_number(s: &[u8], z: &mut Vec<u8>) -> Result<(), String> {
+    let s = std::str::from_utf8(s).map_err(|e| e.to_string())?;
+    let v = s
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("invalid number: {}", s))?;
+    if v == 0.0 {
+        z.push(b'0');
+        return Ok(());
+    }
+    if v < 0.0 {
+        z.push(b'-');
+    }
+    // shortest round-trip digits, as d.ddde[-]x
+    let sci = format!("{:e}", v.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let digits = digits.as_bytes();
+    let k = digits.len() as i32;
+    let n = exp.parse::<i32>().unwrap() + 1;
+    if k <= n && n <= 21 {
+        z.extend_from_slice(digits);
+        z.resize(z.len() + (n - k) as usize, b'0');
+    } else if 0 < n && n <= 21 {
+        z.extend_from_slice(&digits[..n as usize]);
+        z.push(b'.');
+        z.extend_from_slice(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        z.extend_from_slice(b"0.");
+        z.resize(z.len() + (-n) as usize, b'0');
+        z.extend_from_slice(digits);
+    } else {
+        z.push(digits[0]);
+        if k > 1 {
+            z.push(b'.');
+            z.extend_from_slice(&digits[1..]);
+        }
+        z.extend_from_slice(
+            format!("e{}{}", if n > 0 { "+" } else { "-" }, (n - 1).abs()).as_bytes(),
+        );
+    }
+    Ok(())
+}
+
+fn write_canonical_string(s: &[u8], z: &mut Vec<u8>) {
+    z.push(b'"');
+    escape_string_with(s, z, Escape::Canonical);
+    z.push(b'"');
+}
+
+// Sorts the members written to `z` since `starts[0]` by key. `starts` holds the
+// offset of each member, which may begin with the comma that separates it.
+pub fn sort_members(z: &mut Vec<u8>, starts: &[usize]) -> Result<(), String> {
+    if starts.len() < 2 {
+        return Ok(());
+    }
+    let sorted = {
+        let mut members = Vec::with_capacity(starts.len());
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(z.len());
+            let m = z[start..end].strip_prefix(b",").unwrap_or(&z[start..end]);
+            let mut n = 1;
+            while n < m.len() && m[n] != b'"' {
+                n += if m[n] == b'\\' { 2 } else { 1 };
+            }
+            if m.first() != Some(&b'"') || n >= m.len() {
+                return Err(format!("invalid member: {}", String::from_utf8_lossy(m)));
+            }
+            members.push((parse_string(&m[..=n], Surrogates::Reject)?, m));
+        }
+        // https://www.rfc-editor.org/rfc/rfc8785#section-3.2.3
+        members.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+        let mut sorted = Vec::with_capacity(z.len() - starts[0]);
+        if z[starts[0]] == b',' {
+            sorted.push(b',');
+        }
+        for (i, (_, m)) in members.iter().enumerate() {
+            if i != 0 {
+                sorted.push(b',');
+            }
+            sorted.extend_from_slice(m);
+        }
+        sorted
+    };
+    z.truncate(starts[0]);
+    z.extend_from_slice(&sorted);
+    Ok(())
+}
+
+fn write_canonical_token(it: &mut Iter, tok: Token, z: &mut Vec<u8>) -> Result<(), String> {
+    match tok {
+        Token::Null => z.extend_from_slice(b"null"),
+        Token::True => z.extend_from_slice(b"true"),
+        Token::False => z.extend_from_slice(b"false"),
+        Token::Number(s) => write_canonical_number(s, z)?,
+        Token::String(s) => {
+            let mut u = Vec::with_capacity(s.len() - 2);
+            unescape_string(&s[1..s.len() - 1], &mut u)?;
+            write_canonical_string(&u, z);
+        }
+        Token::Array => {
+            z.push(b'[');
+            let mut more = false;
+            loop {
+                match it.next().ok_or("unexpected eof")? {
+                    Token::ArrayClose => break,
+                    Token::Comma if more => z.push(b','),
+                    tok => {
+                        more = true;
+                        write_canonical_token(it, tok, z)?;
+                    }
+                }
+            }
+            z.push(b']');
+        }
+        Token::Object => {
+            let mut members = Vec::new();
+            loop {
+                match it.next().ok_or("unexpected eof")? {
+                    Token::ObjectClose => break,
+                    Token::Comma if !members.is_empty() => continue,
+                    Token::String(k) => {
+                        let key = parse_string(k, Surrogates::Reject)?;
+                        if !matches!(it.next(), Some(Token::Colon)) {
+                            return Err("expected ':'".to_string());
+                        }
+                        let mut value = Vec::new();
+                        let tok = it.next().ok_or("unexpected eof")?;
+                        write_canonical_token(it, tok, &mut value)?;
+                        members.push((key, value));
+                    }
+                    tok => return Err(format!("unexpected token: {:?}", tok)),
+                }
+            }
+            // https://www.rfc-editor.org/rfc/rfc8785#section-3.2.3
+            members.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+            z.push(b'{');
+            for (i, (k, v)) in members.iter().enumerate() {
+                if i != 0 {
+                    z.push(b',');
+                }
+                write_canonical_string(k.as_bytes(), z);
+                z.push(b':');
+                z.extend_from_slice(v);
+            }
+            z.push(b'}');
+        }
+        _ => return Err(format!("unexpected token: {:?}", tok)),
+    }
+    Ok(())
+}
+
+// Rewrites `s` in the canonical form of RFC 8785: no whitespace, members sorted
+// by key, numbers and strings in their shortest form.
+pub fn write_canonical(s: &[u8], z: &mut Vec<u8>) -> Result<(), String> {
+    let mut it = Iter::new(s);
+    let tok = it.next().ok_or("unexpected eof")?;
+    write_canonical_token(&mut it, tok, z)?;
+    match it.next() {
+        None => Ok(()),
+        Some(tok) => Err(format!("unexpected token: {:?}", tok)),
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8259#section-6
+pub fn is_number(s: &[u8]) -> bool {
+    let digits = |i: &mut usize| {
+        let b = *i;
+        while *i < s.len() && s[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        *i - b
+    };
+    let mut i = (s.first() == Some(&b'-')) as usize;
+    match s.get(i) {
+        Some(b'0') => i += 1,
+        Some(b'1'..=b'9') => {
+            digits(&mut i);
+        }
+        _ => return false,
+    }
+    if s.get(i) == Some(&b'.') {
+        i += 1;
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    if matches!(s.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(s.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return false;
+        }
     }
+    i == s.len()
 }
 
 const RAW_MARK: u8 = b'0';
 
 const ESCAPE_TABLE: &[u8] = b"00000000btn0fr00000000000000000000\"000000000000/00000000000000000000000000000000000000000000\\";
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Escape {
+    // Quotes, backslash, slash and control characters only.
+    #[default]
+    Minimal,
+    // Also '<', '>', '&', U+2028 and U+2029, so the output can be embedded in HTML.
+    HtmlSafe,
+    // Also every non-ASCII character, as surrogate pairs beyond the BMP.
+    Ascii,
+    // Quotes, backslash and control characters, leaving '/' alone as RFC 8785 requires.
+    Canonical,
+}
+
+fn escape_unicode(u: u32, z: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    z.extend_from_slice(b"\\u");
+    for shift in [12, 8, 4, 0] {
+        z.push(HEX[((u >> shift) & 0xf) as usize]);
+    }
+}
+
+// Decodes the character at the start of `s`, invalid sequences give U+FFFD.
+fn next_char(s: &[u8]) -> (u32, usize) {
+    let n = match s[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return (0xfffd, 1),
+    };
+    match s.get(..n).map(std::str::from_utf8) {
+        Some(Ok(c)) => (c.chars().next().unwrap() as u32, n),
+        _ => (0xfffd, 1),
+    }
+}
+
 pub fn escape_string(s: &[u8], z: &mut Vec<u8>) {
+    escape_string_with(s, z, Escape::Minimal)
+}
+
+pub fn escape_string_with(s: &[u8], z: &mut Vec<u8>, mode: Escape) {
     let mut last = 0usize;
     let mut i = 0usize;
     while i < s.len() {
         let c = s[i];
-        // todo: validate utf-8 string
-        if c as usize >= ESCAPE_TABLE.len() || ESCAPE_TABLE[c as usize] == RAW_MARK {
-            i += 1;
-            continue;
-        }
-        if last < i {
-            z.extend_from_slice(&s[last..i]);
+        let (u, n) = if c < 0x80 {
+            if (c as usize) < ESCAPE_TABLE.len()
+                && ESCAPE_TABLE[c as usize] != RAW_MARK
+                && (c != b'/' || mode != Escape::Canonical)
+            {
+                z.extend_from_slice(&s[last..i]);
+                z.push(b'\\');
+                z.push(ESCAPE_TABLE[c as usize]);
+                i += 1;
+                last = i;
+                continue;
+            }
+            if c >= 0x20 && (mode != Escape::HtmlSafe || !matches!(c, b'<' | b'>' | b'&')) {
+                i += 1;
+                continue;
+            }
+            (c as u32, 1)
+        } else {
+            match mode {
+                Escape::Minimal | Escape::Canonical => {
+                    i += 1;
+                    continue;
+                }
+                Escape::HtmlSafe => match s[i..] {
+                    [0xe2, 0x80, c @ (0xa8 | 0xa9), ..] => (0x2000 | (c as u32 - 0x80), 3),
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                },
+                Escape::Ascii => next_char(&s[i..]),
+            }
+        };
+        z.extend_from_slice(&s[last..i]);
+        if u > 0xffff {
+            let u = u - 0x10000;
+            escape_unicode(0xd800 | (u >> 10), z);
+            escape_unicode(0xdc00 | (u & 0x3ff), z);
+        } else {
+            escape_unicode(u, z);
         }
-        z.push(b'\\');
-        z.push(ESCAPE_TABLE[c as usize]);
-        i += 1;
+        i += n;
         last = i;
     }
-    if last < s.len() {
-        z.extend_from_slice(&s[last..]);
-    }
+    z.extend_from_slice(&s[last..]);
 }
 
 const UNESCAPE_TABLE: &[u8] = b"0000000000000000000000000000000000\"000000000000/00000000000000000000000000000000000000000000\\00000\x08000\x0C0000000\n000\r0\tu";
 
+// What to do with a `\\uXXXX` surrogate that is not part of a pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Surrogates {
+    #[default]
+    Reject,
+    Replace,
+}
+
+fn read_hex4(s: &[u8]) -> Result<u32, String> {
+    if s.len() < 4 {
+        return Err("invalid escape character".to_string());
+    }
+    let mut uc = 0u32;
+    for &c in &s[..4] {
+        match c {
+            b'0'..=b'9' => uc = uc << 4 | (c - b'0') as u32,
+            b'A'..=b'F' => uc = uc << 4 | (c - b'A' + 10) as u32,
+            b'a'..=b'f' => uc = uc << 4 | (c - b'a' + 10) as u32,
+            _ => return Err(format!("invalid unicode escape sequence: '{}'", c as char)),
+        }
+    }
+    Ok(uc)
+}
+
 pub fn unescape_string(s: &[u8], z: &mut Vec<u8>) -> Result<(), String> {
+    unescape_string_with(s, z, Surrogates::Reject)
+}
+
+pub fn unescape_string_with(s: &[u8], z: &mut Vec<u8>, lone: Surrogates) -> Result<(), String> {
     let mut i = 0usize;
     while i < s.len() {
         let c = s[i];
         if c == b'\\' {
             i += 1;
-            let c = s[i];
+            let c = *s.get(i).ok_or("invalid escape character")?;
             if c as usize >= UNESCAPE_TABLE.len() || UNESCAPE_TABLE[c as usize] == RAW_MARK {
                 return Err(format!("invalid escape character: '{}'", c as char));
             }
             if c == b'u' {
-                if i + 4 >= s.len() {
-                    return Err("invalid escape character".to_string());
-                }
-                let mut uc = 0u32;
-                for _ in 0..4 {
-                    i += 1;
-                    let c = s[i];
-                    match c {
-                        b'0'..=b'9' => uc = uc << 4 | (c - b'0') as u32,
-                        b'A'..=b'F' => uc = uc << 4 | (c - b'A' + 10) as u32,
-                        b'a'..=b'f' => uc = uc << 4 | (c - b'a' + 10) as u32,
-                        _ => {
-                            return Err(format!("invalid unicode escape sequence: '{}'", c as char))
-                        }
+                let mut uc = read_hex4(&s[i + 1..])?;
+                i += 4;
+                if (0xd800..0xdc00).contains(&uc) && s[i + 1..].starts_with(b"\\u") {
+                    // https://www.rfc-editor.org/rfc/rfc8259#section-7
+                    let lo = read_hex4(&s[i + 3..])?;
+                    if (0xdc00..0xe000).contains(&lo) {
+                        uc = 0x10000 + ((uc - 0xd800) << 10) + (lo - 0xdc00);
+                        i += 6;
                     }
                 }
-                if let Some(c) = ::std::char::from_u32(uc) {
-                    let mut dst = [0u8; 6];
-                    let cs = c.encode_utf8(&mut dst);
-                    z.extend_from_slice(cs.as_bytes());
-                } else {
-                    return Err(format!("invalid unicode character: '{:x}'", uc));
-                }
+                let c = match ::std::char::from_u32(uc) {
+                    Some(c) => c,
+                    None if lone == Surrogates::Replace => ::std::char::REPLACEMENT_CHARACTER,
+                    None => return Err(format!("lone surrogate: '\\u{:04x}'", uc)),
+                };
+                let mut dst = [0u8; 4];
+                z.extend_from_slice(c.encode_utf8(&mut dst).as_bytes());
             } else {
                 z.push(UNESCAPE_TABLE[c as usize]);
             }
@@ -193,6 +644,135 @@ pub fn unescape_string(s: &[u8], z: &mut Vec<u8>) -> Result<(), String> {
     Ok(())
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn write_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => buf.extend_from_slice(b"null"),
+            Value::Bool(b) => buf.extend_from_slice(if *b { b"true" } else { b"false" }),
+            Value::Number(n) => buf.extend_from_slice(n.as_bytes()),
+            Value::String(s) => {
+                buf.push(b'"');
+                escape_string(s.as_bytes(), buf);
+                buf.push(b'"');
+            }
+            Value::Array(elems) => {
+                buf.push(b'[');
+                for (i, v) in elems.iter().enumerate() {
+                    if i != 0 {
+                        buf.push(b',');
+                    }
+                    v.write_into(buf);
+                }
+                buf.push(b']');
+            }
+            Value::Object(members) => {
+                buf.push(b'{');
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i != 0 {
+                        buf.push(b',');
+                    }
+                    buf.push(b'"');
+                    escape_string(k.as_bytes(), buf);
+                    buf.extend_from_slice(b"\":");
+                    v.write_into(buf);
+                }
+                buf.push(b'}');
+            }
+        }
+    }
+}
+
+fn parse_string(s: &[u8], lone: Surrogates) -> Result<String, String> {
+    let mut z = Vec::with_capacity(s.len() - 2);
+    unescape_string_with(&s[1..s.len() - 1], &mut z, lone)?;
+    String::from_utf8(z).map_err(|e| e.to_string())
+}
+
+pub fn parse_token(it: &mut Iter, tok: Token) -> Result<Value, String> {
+    parse_token_with(it, tok, Surrogates::Reject)
+}
+
+pub fn parse_token_with(it: &mut Iter, tok: Token, lone: Surrogates) -> Result<Value, String> {
+    match tok {
+        Token::Null => Ok(Value::Null),
+        Token::False => Ok(Value::Bool(false)),
+        Token::True => Ok(Value::Bool(true)),
+        Token::Number(s) => Ok(Value::Number(String::from_utf8_lossy(s).into_owned())),
+        Token::String(s) => parse_string(s, lone).map(Value::String),
+        Token::Array => {
+            let mut elems = Vec::new();
+            loop {
+                let tok = it.next().ok_or("unexpected eof")?;
+                match tok {
+                    Token::ArrayClose if elems.is_empty() => return Ok(Value::Array(elems)),
+                    _ => elems.push(parse_token_with(it, tok, lone)?),
+                }
+                match it.next() {
+                    Some(Token::Comma) => {}
+                    Some(Token::ArrayClose) => return Ok(Value::Array(elems)),
+                    Some(tok) => return Err(format!("unexpected token: {:?}", tok)),
+                    None => return Err("unexpected eof".to_string()),
+                }
+            }
+        }
+        Token::Object => {
+            let mut members = Vec::new();
+            loop {
+                let tok = it.next().ok_or("unexpected eof")?;
+                let key = match tok {
+                    Token::ObjectClose if members.is_empty() => return Ok(Value::Object(members)),
+                    Token::String(k) => parse_string(k, lone)?,
+                    _ => return Err(format!("unexpected token: {:?}", tok)),
+                };
+                if !matches!(it.next(), Some(Token::Colon)) {
+                    return Err("expected ':'".to_string());
+                }
+                let tok = it.next().ok_or("unexpected eof")?;
+                let val = parse_token_with(it, tok, lone)?;
+                members.push((key, val));
+                match it.next() {
+                    Some(Token::Comma) => {}
+                    Some(Token::ObjectClose) => return Ok(Value::Object(members)),
+                    Some(tok) => return Err(format!("unexpected token: {:?}", tok)),
+                    None => return Err("unexpected eof".to_string()),
+                }
+            }
+        }
+        _ => Err(format!("unexpected token: {:?}", tok)),
+    }
+}
+
+pub fn parse_value(it: &mut Iter) -> Result<Value, String> {
+    match it.next() {
+        Some(tok) => parse_token(it, tok),
+        None => Err("unexpected eof".to_string()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,6 +786,163 @@ mod tests {
         }
     }
 
+    // https://www.rfc-editor.org/rfc/rfc8259
+    #[test]
+    fn test_tokenizer_conformance() {
+        let valid = |s: &[u8]| Iter::new(s).all(|t| !matches!(t, Token::Invalid(_)));
+        for s in [
+            &b"null"[..],
+            b"true",
+            b"false",
+            b" \t\r\n[ ]",
+            b"0",
+            b"-0",
+            b"1e+5",
+            b"1E-5",
+            b"-1.5e10",
+            b"123.456",
+            b"\"\"",
+            br#""a\\""#,
+            br#""\"\\\/\b\f\n\r\t""#,
+            br#""\u00e9\uD83D\uDE00""#,
+            "\"é😀\u{7f}\"".as_bytes(),
+            br#"{"a":[1,2.5,{"b":null}],"c":"d"}"#,
+        ] {
+            assert!(valid(s), "{}", String::from_utf8_lossy(s));
+        }
+        for s in [
+            &b"nxyz"[..],
+            b"nul",
+            b"True",
+            b"fals",
+            b"1-2-",
+            b"01",
+            b"1.",
+            b".5",
+            b"1e",
+            b"1e+",
+            b"-",
+            b"+1",
+            b"0x10",
+            b"\"abc",
+            b"\"a\\\"",
+            b"\"\\x\"",
+            b"\"\\u12\"",
+            b"\"\\u12g4\"",
+            b"\"a\tb\"",
+            b"\"a\nb\"",
+            b"\"\x00\"",
+            b"'a'",
+            b"\x0c1",
+        ] {
+            assert!(!valid(s), "{}", String::from_utf8_lossy(s));
+        }
+
+        let tokens = |s: &'static [u8]| Iter::new(s).collect::<Vec<_>>();
+        assert_eq!(tokens(br#""a\\""#), vec![Token::String(br#""a\\""#)]);
+        assert_eq!(
+            tokens(br#"["a\\",1e+5]"#),
+            vec![
+                Token::Array,
+                Token::String(br#""a\\""#),
+                Token::Comma,
+                Token::Number(b"1e+5"),
+                Token::ArrayClose,
+            ]
+        );
+        assert_eq!(tokens(b"nxyz")[0], Token::Invalid(b"nxyz"));
+        assert_eq!(tokens(b"1-2-")[0], Token::Invalid(b"1-2-"));
+    }
+
+    #[test]
+    fn test_write_pretty() {
+        let s = br#"{"a":[1,{"b":null}],"c":{},"d":[],"e":"x:,{"}"#;
+        let mut z = Vec::new();
+        write_pretty(s, &mut z, &Pretty::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(z).unwrap(),
+            "{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"c\": {},\n  \"d\": [],\n  \"e\": \"x:,{\"\n}"
+        );
+        let pretty = Pretty {
+            indent: Indent::Tab,
+            space_after_colon: false,
+        };
+        let mut z = Vec::new();
+        write_pretty(br#"[true,{"a":false}]"#, &mut z, &pretty).unwrap();
+        assert_eq!(
+            String::from_utf8(z).unwrap(),
+            "[\n\ttrue,\n\t{\n\t\t\"a\":false\n\t}\n]"
+        );
+        for s in [
+            &b"[1,tru]"[..],
+            b"{\"a\":1",
+            b"[]]",
+            b"{\"a\":1]",
+            b"[1:2]",
+            b"{,}",
+            b"[1,,2]",
+            b"[1,]",
+            b"{\"a\"}",
+            b"{1:2}",
+            b"1 2",
+            b"",
+        ] {
+            let mut z = Vec::new();
+            assert!(
+                write_pretty(s, &mut z, &pretty).is_err(),
+                "{}",
+                String::from_utf8_lossy(s)
+            );
+        }
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc8785#section-3.2.2
+    #[test]
+    fn test_write_canonical() {
+        let canonical = |s: &[u8]| {
+            let mut z = Vec::new();
+            write_canonical(s, &mut z).map(|_| String::from_utf8(z).unwrap())
+        };
+        assert_eq!(
+            canonical(
+                br#"{
+                    "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+                    "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+                    "literals": [null, true, false]
+                }"#
+            )
+            .unwrap(),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+        assert_eq!(
+            canonical(
+                br#"{"\u20ac":1,"\r":2,"\ufb33":3,"1":4,"\ud83d\ude00":5,"\u0080":6,"\u00f6":7}"#
+            )
+            .unwrap(),
+            "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"ö\":7,\"€\":1,\"😀\":5,\"\u{fb33}\":3}"
+        );
+        for (s, out) in [
+            ("-0", "0"),
+            ("0.0", "0"),
+            ("1e21", "1e+21"),
+            ("1e20", "100000000000000000000"),
+            ("123e-2", "1.23"),
+            ("0.000001", "0.000001"),
+            ("-1e-7", "-1e-7"),
+            ("1.5e300", "1.5e+300"),
+            ("4294967295", "4294967295"),
+        ] {
+            assert_eq!(canonical(s.as_bytes()).unwrap(), out, "{}", s);
+        }
+        let mut z = b"{\"b\":1,\"\\u00e9\":[2],\"a\":{\"y\":3}".to_vec();
+        sort_members(&mut z, &[1, 6, 19]).unwrap();
+        assert_eq!(z, b"{\"a\":{\"y\":3},\"b\":1,\"\\u00e9\":[2]");
+
+        assert!(canonical(b"1e400").is_err());
+        assert!(canonical(b"[1,2").is_err());
+        assert!(canonical(b"{} {}").is_err());
+    }
+
     #[test]
     fn test_escape_string() {
         let mut o1 = Vec::new();
@@ -214,6 +951,26 @@ mod tests {
         let mut o2 = Vec::new();
         escape_string(b"123\tabc", &mut o2);
         assert_eq!(String::from_utf8(o2).unwrap(), "123\\tabc");
+
+        let s = "a\x01\"</b>&\u{2028}é😀".as_bytes();
+        for (mode, out) in [
+            (Escape::Minimal, "a\\u0001\\\"<\\/b>&\u{2028}é😀"),
+            (
+                Escape::HtmlSafe,
+                "a\\u0001\\\"\\u003c\\/b\\u003e\\u0026\\u2028é😀",
+            ),
+            (
+                Escape::Ascii,
+                "a\\u0001\\\"<\\/b>&\\u2028\\u00e9\\ud83d\\ude00",
+            ),
+        ] {
+            let mut z = Vec::new();
+            escape_string_with(s, &mut z, mode);
+            assert_eq!(String::from_utf8(z).unwrap(), out);
+        }
+        let mut z = Vec::new();
+        escape_string_with(b"\xffa\xe4\xbd", &mut z, Escape::Ascii);
+        assert_eq!(z, b"\\ufffda\\ufffd\\ufffd");
     }
 
     #[test]
@@ -224,5 +981,68 @@ mod tests {
         let mut o2 = Vec::new();
         unescape_string(b"\\u4f60\\u597d", &mut o2).unwrap();
         assert_eq!(String::from_utf8(o2).unwrap(), "你好");
+
+        let mut z = Vec::new();
+        unescape_string(b"\\ud83d\\uDE00!", &mut z).unwrap();
+        assert_eq!(String::from_utf8(z).unwrap(), "😀!");
+        for s in [&b"\\ud83d"[..], b"\\ude00", b"\\ud83da", b"\\ud83d\\u0041"] {
+            let mut z = Vec::new();
+            assert!(unescape_string(s, &mut z)
+                .unwrap_err()
+                .starts_with("lone surrogate: '\\ud"));
+        }
+        let mut z = Vec::new();
+        unescape_string_with(b"\\ud83d\\u0041\\ude00\\ud83d", &mut z, Surrogates::Replace).unwrap();
+        assert_eq!(String::from_utf8(z).unwrap(), "\u{fffd}A\u{fffd}\u{fffd}");
+        let mut z = Vec::new();
+        assert!(unescape_string(b"\\ud83d\\u00", &mut z).is_err());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let v = parse_value(&mut Iter::new(br#"{"a":[1,"x\ty",null],"b":{"c":true}}"#)).unwrap();
+        assert_eq!(
+            v,
+            Value::Object(vec![
+                (
+                    "a".to_string(),
+                    Value::Array(vec![
+                        Value::Number("1".to_string()),
+                        Value::String("x\ty".to_string()),
+                        Value::Null,
+                    ])
+                ),
+                (
+                    "b".to_string(),
+                    Value::Object(vec![("c".to_string(), Value::Bool(true))])
+                ),
+            ])
+        );
+        assert_eq!(
+            v.get("b").and_then(|b| b.get("c")),
+            Some(&Value::Bool(true))
+        );
+        for s in [
+            &b"{\"a\" 1}"[..],
+            b"[1 2]",
+            b"[1,,2]",
+            b"[,1]",
+            b"[1,]",
+            b"{\"a\":1 \"b\":2}",
+            b"{\"a\":1,,\"b\":2}",
+            b"{,\"a\":1}",
+            b"{\"a\":1,}",
+            b"[1",
+        ] {
+            assert!(
+                parse_value(&mut Iter::new(s)).is_err(),
+                "{}",
+                String::from_utf8_lossy(s)
+            );
+        }
+
+        let mut buf = Vec::new();
+        v.write_into(&mut buf);
+        assert_eq!(buf, br#"{"a":[1,"x\ty",null],"b":{"c":true}}"#);
     }
 }
@@ -0,0 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::json::{parse_value, Iter, Value};
+use crate::metadata::*;
+use crate::{Error, Result};
+
+// https://json-schema.org/draft/2020-12/json-schema-core
+
+const TAG_KEYWORD: &str = "x-protobuf-tag";
+
+// Field numbers reserved for the protobuf implementation.
+const RESERVED_TAGS: std::ops::Range<u32> = 19000..20000;
+
+fn error(msg: String) -> Error {
+    Error::Wrap(msg.into())
+}
+
+fn upper_camel_case(s: &str) -> String {
+    let mut z = String::with_capacity(s.len());
+    let mut upper = true;
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            if upper {
+                z.push(c.to_ascii_uppercase());
+                upper = false;
+            } else {
+                z.push(c);
+            }
+        } else {
+            upper = true;
+        }
+    }
+    z
+}
+
+fn schema_type(schema: &Value) -> Option<&str> {
+    match schema.get("type") {
+        Some(Value::String(s)) => Some(s.as_str()),
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|&t| t != "null"),
+        _ => {
+            if schema.get("properties").is_some() || schema.get("additionalProperties").is_some() {
+                Some("object")
+            } else if schema.get("items").is_some() {
+                Some("array")
+            } else {
+                match schema.get("enum").or_else(|| schema.get("const")) {
+                    Some(Value::Array(values)) => values.first().and_then(value_type),
+                    Some(v) => value_type(v),
+                    None => None,
+                }
+            }
+        }
+    }
+}
+
+fn value_type(v: &Value) -> Option<&'static str> {
+    match v {
+        Value::Bool(_) => Some("boolean"),
+        Value::Number(n) if n.contains(['.', 'e', 'E']) => Some("number"),
+        Value::Number(_) => Some("integer"),
+        Value::String(_) => Some("string"),
+        _ => None,
+    }
+}
+
+fn nullable_variant(schema: &Value) -> Option<&Value> {
+    let variants = match schema.get("oneOf").or_else(|| schema.get("anyOf")) {
+        Some(Value::Array(variants)) => variants,
+        _ => return None,
+    };
+    let mut it = variants
+        .iter()
+        .filter(|v| !matches!(v.get("type").and_then(Value::as_str), Some("null")));
+    match (it.next(), it.next()) {
+        (Some(v), None) => Some(v),
+        _ => None,
+    }
+}
+
+struct Importer<'a> {
+    root: &'a Value,
+    package: &'a str,
+    refs: HashMap<String, Kind>,
+    // message name -> the $ref it was built from
+    ref_names: HashMap<String, String>,
+    resolving: Vec<String>,
+}
+
+impl<'a> Importer<'a> {
+    fn resolve(&self, r: &str) -> Result<&'a Value> {
+        let pointer = r
+            .strip_prefix('#')
+            .ok_or_else(|| error(format!("unsupported $ref: {}", r)))?;
+        let mut v = self.root;
+        for seg in pointer.split('/').skip(1) {
+            let seg = seg.replace("~1", "/").replace("~0", "~");
+            v = match v {
+                Value::Object(_) => v.get(&seg),
+                Value::Array(elems) => seg.parse::<usize>().ok().and_then(|i| elems.get(i)),
+                _ => None,
+            }
+            .ok_or_else(|| error(format!("unresolved $ref: {}", r)))?;
+        }
+        Ok(v)
+    }
+
+    fn ref_kind(&mut self, r: &str) -> Result<Kind> {
+        if let Some(kind) = self.refs.get(r) {
+            return Ok(kind.clone());
+        }
+        if self.resolving.iter().any(|x| x == r) {
+            return Err(error(format!("recursive $ref is not supported: {}", r)));
+        }
+        let target = self.resolve(r)?;
+        let name = upper_camel_case(r.rsplit('/').next().unwrap_or_default());
+        let name = if self.package.is_empty() {
+            name
+        } else {
+            format!("{}.{}", self.package, name)
+        };
+        self.resolving.push(r.to_string());
+        let kind = self.kind(target, &name);
+        self.resolving.pop();
+        let kind = kind?;
+        if let Kind::Message(_) | Kind::Map(_) = kind {
+            if let Some(other) = self.ref_names.insert(name.clone(), r.to_string()) {
+                return Err(error(format!(
+                    "$ref {} and {} both name message {}",
+                    other, r, name
+                )));
+            }
+        }
+        self.refs.insert(r.to_string(), kind.clone());
+        Ok(kind)
+    }
+
+    fn kind(&mut self, schema: &'a Value, name: &str) -> Result<Kind> {
+        if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+            return self.ref_kind(r);
+        }
+        if let Some(v) = nullable_variant(schema) {
+            return self.kind(v, name);
+        }
+        let format = schema.get("format").and_then(Value::as_str).unwrap_or("");
+        match schema_type(schema) {
+            Some("string") => match format {
+                "byte" | "binary" => Ok(Kind::Bytes),
+                "int64" => Ok(Kind::Int64),
+                "uint64" => Ok(Kind::Uint64),
+                _ => Ok(Kind::String),
+            },
+            Some("integer") => match format {
+                "int32" => Ok(Kind::Int32),
+                "uint32" => Ok(Kind::Uint32),
+                "uint64" => Ok(Kind::Uint64),
+                _ => Ok(Kind::Int64),
+            },
+            Some("number") => match format {
+                "float" => Ok(Kind::Float),
+                _ => Ok(Kind::Double),
+            },
+            Some("boolean") => Ok(Kind::Bool),
+            Some("object") => {
+                let additional = schema.get("additionalProperties");
+                if schema.get("properties").is_none()
+                    && matches!(additional, Some(Value::Object(_)))
+                {
+                    self.map_kind(additional.unwrap(), name)
+                } else {
+                    self.message(schema, name)
+                        .map(|m| Kind::Message(Rc::new(m)))
+                }
+            }
+            Some("array") => Err(error(format!("nested array is not supported: {}", name))),
+            Some(t) => Err(error(format!("unsupported type '{}': {}", t, name))),
+            None => Err(error(format!("untyped schema: {}", name))),
+        }
+    }
+
+    fn map_kind(&mut self, schema: &'a Value, name: &str) -> Result<Kind> {
+        let value = self.field(schema, name, "value", 2, false)?;
+        if value.repeated || matches!(value.kind, Kind::Map(_)) {
+            return Err(error(format!("unsupported map value: {}", name)));
+        }
+        let key = Field {
+            name: "key".to_string(),
+            tag: 1,
+            kind: Kind::String,
+            repeated: false,
+            required: false,
+        };
+        Ok(Kind::Map(Rc::new(Message::new(
+            format!("{}Entry", name),
+            vec![key, value],
+            true,
+        ))))
+    }
+
+    fn field(
+        &mut self,
+        schema: &'a Value,
+        parent: &str,
+        name: &str,
+        tag: u32,
+        required: bool,
+    ) -> Result<Field> {
+        let schema = nullable_variant(schema).unwrap_or(schema);
+        let type_name = format!("{}.{}", parent, upper_camel_case(name));
+        let (kind, repeated) =
+            if schema.get("$ref").is_none() && schema_type(schema) == Some("array") {
+                let items = schema
+                    .get("items")
+                    .ok_or_else(|| error(format!("array without items: {}", type_name)))?;
+                let kind = self.kind(items, &type_name)?;
+                if let Kind::Map(_) = kind {
+                    return Err(error(format!(
+                        "array of maps is not supported: {}",
+                        type_name
+                    )));
+                }
+                (kind, true)
+            } else {
+                (self.kind(schema, &type_name)?, false)
+            };
+        let required = required && !repeated && !matches!(kind, Kind::Map(_));
+        Ok(Field {
+            name: name.to_string(),
+            tag,
+            kind,
+            repeated,
+            required,
+        })
+    }
+
+    fn message(&mut self, schema: &'a Value, name: &str) -> Result<Message> {
+        let required = match schema.get("required") {
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let mut props = match schema.get("properties") {
+            Some(Value::Object(props)) => props.iter().collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+        props.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut tags = Vec::with_capacity(props.len());
+        for (prop, sub) in props.iter() {
+            let tag = match sub.get(TAG_KEYWORD) {
+                Some(Value::Number(n)) => match n.parse::<u32>() {
+                    Ok(tag)
+                        if (1..1 << 29).contains(&tag)
+                            && !RESERVED_TAGS.contains(&tag)
+                            && !tags.contains(&Some(tag)) =>
+                    {
+                        Some(tag)
+                    }
+                    _ => return Err(error(format!("invalid {}: {}.{}", TAG_KEYWORD, name, prop))),
+                },
+                Some(_) => {
+                    return Err(error(format!("invalid {}: {}.{}", TAG_KEYWORD, name, prop)))
+                }
+                None => None,
+            };
+            tags.push(tag);
+        }
+        let pinned = tags.iter().flatten().copied().collect::<HashSet<_>>();
+        let mut next = 1;
+        let mut fields = Vec::with_capacity(props.len());
+        for ((prop, sub), tag) in props.into_iter().zip(tags.iter()) {
+            let tag = match *tag {
+                Some(tag) => tag,
+                None => {
+                    while pinned.contains(&next) || RESERVED_TAGS.contains(&next) {
+                        next += 1;
+                    }
+                    next += 1;
+                    next - 1
+                }
+            };
+            let is_required = required.contains(&prop.as_str());
+            fields.push(self.field(sub, name, prop, tag, is_required)?);
+        }
+        Ok(Message::new(name.to_string(), fields, true))
+    }
+}
+
+// A property can pin its field number with "x-protobuf-tag". The others take the lowest unused
+// numbers in the byte order of their property names, so tags never depend on property order.
+// Pin the existing properties before adding new ones to keep their tags stable.
+// Messages from `$ref` targets are named in the package of `name` after the last segment of the
+// reference; two references that end up with the same name are rejected.
+pub fn import_json_schema(s: &[u8], name: &str) -> Result<Message> {
+    let root = parse_value(&mut Iter::new(s)).map_err(error)?;
+    let mut importer = Importer {
+        root: &root,
+        package: name.rsplit_once('.').map(|x| x.0).unwrap_or_default(),
+        refs: HashMap::new(),
+        ref_names: HashMap::new(),
+        resolving: Vec::new(),
+    };
+    match schema_type(&root) {
+        Some("object") => importer.message(&root, name),
+        _ => Err(error(format!("root schema must be an object: {}", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::{Decoder, Encoder};
+    use crate::{trans_json_to_proto, trans_proto_to_json};
+
+    use super::*;
+
+    const SCHEMA: &str = r##"{
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "required": ["id"],
+        "properties": {
+            "id": {"type": "string"},
+            "count": {"type": "integer", "format": "int32"},
+            "score": {"type": ["number", "null"]},
+            "tags": {"type": "array", "items": {"type": "string"}},
+            "owner": {"$ref": "#/$defs/user"},
+            "labels": {"type": "object", "additionalProperties": {"type": "string"}},
+            "blob": {"type": "string", "format": "byte"},
+            "members": {"type": "array", "items": {"$ref": "#/$defs/user"}}
+        },
+        "$defs": {
+            "user": {
+                "type": "object",
+                "properties": {"name": {"type": "string"}, "admin": {"type": "boolean"}}
+            }
+        }
+    }"##;
+
+    #[test]
+    fn test_import_json_schema() {
+        let msg = import_json_schema(SCHEMA.as_bytes(), "partner.Payload").unwrap();
+        let fields = msg
+            .get_fields()
+            .iter()
+            .map(|f| (f.name.as_str(), f.tag, f.repeated, f.required))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("blob", 1, false, false),
+                ("count", 2, false, false),
+                ("id", 3, false, true),
+                ("labels", 4, false, false),
+                ("members", 5, true, false),
+                ("owner", 6, false, false),
+                ("score", 7, false, false),
+                ("tags", 8, true, false),
+            ]
+        );
+        assert!(matches!(msg.get_by_name("blob").unwrap().kind, Kind::Bytes));
+        assert!(matches!(
+            msg.get_by_name("count").unwrap().kind,
+            Kind::Int32
+        ));
+        assert!(matches!(
+            msg.get_by_name("score").unwrap().kind,
+            Kind::Double
+        ));
+        match (
+            &msg.get_by_name("owner").unwrap().kind,
+            &msg.get_by_name("members").unwrap().kind,
+        ) {
+            (Kind::Message(a), Kind::Message(b)) => {
+                assert!(Rc::ptr_eq(a, b));
+                assert_eq!(a.get_name(), "partner.User");
+            }
+            _ => panic!("expect message kind"),
+        }
+        match msg.get_by_name("labels").unwrap().kind {
+            Kind::Map(ref entry) => {
+                assert_eq!(entry.get_name(), "partner.Payload.LabelsEntry");
+                assert!(matches!(entry.get_fields()[1].kind, Kind::String));
+            }
+            _ => panic!("expect map kind"),
+        }
+    }
+
+    #[test]
+    fn test_import_json_schema_tags() {
+        let tags = |s: &str| {
+            import_json_schema(s.as_bytes(), "Tagged")
+                .unwrap()
+                .get_fields()
+                .iter()
+                .map(|f| (f.name.clone(), f.tag))
+                .collect::<HashMap<_, _>>()
+        };
+        let a = tags(
+            r#"{"properties": {"b": {"type": "string", "x-protobuf-tag": 1}, "c": {"type": "string", "x-protobuf-tag": 3}}}"#,
+        );
+        let b = tags(
+            r#"{"properties": {"a": {"type": "string"}, "c": {"type": "string", "x-protobuf-tag": 3}, "b": {"type": "string", "x-protobuf-tag": 1}}}"#,
+        );
+        assert_eq!((a["b"], a["c"]), (1, 3));
+        assert_eq!((b["a"], b["b"], b["c"]), (2, 1, 3));
+
+        let props = (0..19001)
+            .map(|i| format!(r#""p{:05}": {{"type": "string"}}"#, i))
+            .collect::<Vec<_>>()
+            .join(",");
+        let many = tags(&format!(
+            r#"{{"properties": {{"a": {{"type": "string", "x-protobuf-tag": 18999}}, {}}}}}"#,
+            props
+        ));
+        assert_eq!(
+            (many["p18997"], many["p18998"], many["p18999"]),
+            (18998, 20000, 20001)
+        );
+
+        for s in [
+            r#"{"properties": {"a": {"type": "string", "x-protobuf-tag": 0}}}"#,
+            r#"{"properties": {"a": {"type": "string", "x-protobuf-tag": "1"}}}"#,
+            r#"{"properties": {"a": {"type": "string", "x-protobuf-tag": 2}, "b": {"type": "string", "x-protobuf-tag": 2}}}"#,
+            r#"{"properties": {"a": {"type": "string", "x-protobuf-tag": 19000}}}"#,
+        ] {
+            assert!(import_json_schema(s.as_bytes(), "Bad").is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_import_json_schema_round_trip() {
+        let msg = import_json_schema(SCHEMA.as_bytes(), "partner.Payload").unwrap();
+        let s = r#"{"blob":"aGk=","count":3,"id":"x","labels":{"k":"v"},"members":[{"admin":true,"name":"a"}],"owner":{"name":"b"},"score":1.5,"tags":["t0","t1"]}"#;
+        let mut enc = Encoder::new();
+        trans_json_to_proto(&mut enc, &mut Iter::new(s.as_bytes()), &msg).unwrap();
+        let mut buf = Vec::new();
+        trans_proto_to_json(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg).unwrap();
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), s);
+    }
+
+    #[test]
+    fn test_import_json_schema_unsupported() {
+        let cases = [
+            r##"{"type": "array", "items": {"type": "string"}}"##,
+            r##"{"properties": {"a": {"type": "array", "items": {"type": "array"}}}}"##,
+            r##"{"properties": {"a": {"$ref": "#/$defs/missing"}}}"##,
+            r##"{"properties": {"a": {"$ref": "#/$defs/n"}}, "$defs": {"n": {"properties": {"n": {"$ref": "#/$defs/n"}}}}}"##,
+        ];
+        for s in cases {
+            assert!(import_json_schema(s.as_bytes(), "x.Bad").is_err(), "{}", s);
+        }
+
+        let s = r##"{
+            "properties": {
+                "a": {"$ref": "#/$defs/a/properties/Item"},
+                "b": {"$ref": "#/definitions/Item"}
+            },
+            "$defs": {"a": {"properties": {"Item": {"properties": {"x": {"type": "string"}}}}}},
+            "definitions": {"Item": {"properties": {"y": {"type": "string"}}}}
+        }"##;
+        match import_json_schema(s.as_bytes(), "x.Bad") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "wrap: $ref #/$defs/a/properties/Item and #/definitions/Item both name message x.Item"
+            ),
+            Ok(_) => panic!("expect a name collision"),
+        }
+    }
+}
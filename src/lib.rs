@@ -1,7 +1,11 @@
 pub use trans::*;
 
+pub mod descriptor;
 pub mod json;
+pub mod jsonschema;
+pub mod lint;
 pub mod metadata;
+pub mod openapi;
 pub mod proto;
 
 mod trans;
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::json::Value;
+use crate::metadata::*;
+
+// https://protobuf.dev/programming-guides/style/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    FieldNameCase,
+    TagGap,
+    TagReuse,
+    EnumZeroValue,
+    MapMessageKey,
+    JsonNameCollision,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::FieldNameCase => "field-name-case",
+            Rule::TagGap => "tag-gap",
+            Rule::TagReuse => "tag-reuse",
+            Rule::EnumZeroValue => "enum-zero-value",
+            Rule::MapMessageKey => "map-message-key",
+            Rule::JsonNameCollision => "json-name-collision",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub path: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn write_into(&self, buf: &mut Vec<u8>) {
+        Value::Object(vec![
+            (
+                "rule".to_string(),
+                Value::String(self.rule.as_str().to_string()),
+            ),
+            ("path".to_string(), Value::String(self.path.clone())),
+            ("message".to_string(), Value::String(self.message.clone())),
+        ])
+        .write_into(buf)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.path, self.rule.as_str(), self.message)
+    }
+}
+
+fn is_snake_case(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_lowercase())
+        && !s.ends_with('_')
+        && !s.contains("__")
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// Same rule protoc uses to derive `json_name`.
+fn json_name(s: &str) -> String {
+    let mut z = String::with_capacity(s.len());
+    let mut upper = false;
+    for c in s.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            z.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            z.push(c);
+        }
+    }
+    z
+}
+
+struct Linter {
+    diags: Vec<Diagnostic>,
+    visited: HashSet<String>,
+}
+
+impl Linter {
+    fn report(&mut self, rule: Rule, path: String, message: String) {
+        self.diags.push(Diagnostic {
+            rule,
+            path,
+            message,
+        });
+    }
+
+    fn check_tags(&mut self, msg: &Message) {
+        let reserved = msg.get_reserved();
+        let mut tags = msg
+            .get_fields()
+            .iter()
+            .map(|f| (f.tag, f.name.as_str()))
+            .collect::<Vec<_>>();
+        tags.sort();
+        let mut next = 1u32;
+        for (i, &(tag, name)) in tags.iter().enumerate() {
+            let path = format!("{}.{}", msg.get_name(), name);
+            if i > 0 && tags[i - 1].0 == tag {
+                self.report(
+                    Rule::TagReuse,
+                    path.clone(),
+                    format!("tag {} is already used by '{}'", tag, tags[i - 1].1),
+                );
+            }
+            if reserved.iter().any(|r| r.contains(&tag)) {
+                self.report(
+                    Rule::TagReuse,
+                    path.clone(),
+                    format!("tag {} is reserved", tag),
+                );
+            }
+            while next < tag {
+                match reserved.iter().find(|r| r.contains(&next)) {
+                    Some(r) => next = r.end,
+                    None => {
+                        let end = reserved
+                            .iter()
+                            .map(|r| r.start)
+                            .filter(|&s| s > next && s < tag)
+                            .min()
+                            .unwrap_or(tag);
+                        self.report(
+                            Rule::TagGap,
+                            path.clone(),
+                            format!("tags {}..{} are neither used nor reserved", next, end),
+                        );
+                        next = end;
+                    }
+                }
+            }
+            next = next.max(tag.saturating_add(1));
+        }
+    }
+
+    fn check_names(&mut self, msg: &Message) {
+        let mut json_names: Vec<(String, &str)> = Vec::new();
+        for f in msg.get_fields() {
+            let path = format!("{}.{}", msg.get_name(), f.name);
+            if !is_snake_case(&f.name) {
+                self.report(
+                    Rule::FieldNameCase,
+                    path.clone(),
+                    format!("field name '{}' is not snake_case", f.name),
+                );
+            }
+            let name = json_name(&f.name);
+            if let Some(other) = json_names.iter().find(|x| x.0 == name) {
+                self.report(
+                    Rule::JsonNameCollision,
+                    path,
+                    format!("json name '{}' collides with field '{}'", name, other.1),
+                );
+            } else {
+                json_names.push((name, f.name.as_str()));
+            }
+        }
+    }
+
+    fn message(&mut self, msg: &Message) {
+        if !self.visited.insert(msg.get_name().to_string()) {
+            return;
+        }
+        self.check_names(msg);
+        self.check_tags(msg);
+        for f in msg.get_fields() {
+            match f.kind {
+                Kind::Message(ref m) => self.message(m),
+                Kind::Map(ref entry) => {
+                    let fields = entry.get_fields();
+                    if matches!(fields[0].kind, Kind::Message(_) | Kind::Map(_)) {
+                        self.report(
+                            Rule::MapMessageKey,
+                            format!("{}.{}", msg.get_name(), f.name),
+                            "map key must be a scalar type".to_string(),
+                        );
+                    }
+                    for v in fields.iter() {
+                        if let Kind::Message(ref m) = v.kind {
+                            self.message(m);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn enumeration(&mut self, e: &Enum) {
+        for v in e.values.iter() {
+            if e.reserved.iter().any(|r| r.contains(&v.number)) {
+                self.report(
+                    Rule::TagReuse,
+                    format!("{}.{}", e.name, v.name),
+                    format!("number {} is reserved", v.number),
+                );
+            }
+        }
+        match e.values.iter().find(|v| v.number == 0) {
+            Some(v) if v.name.ends_with("_UNSPECIFIED") => {}
+            Some(v) => self.report(
+                Rule::EnumZeroValue,
+                format!("{}.{}", e.name, v.name),
+                "zero value should be named *_UNSPECIFIED".to_string(),
+            ),
+            None => self.report(
+                Rule::EnumZeroValue,
+                e.name.clone(),
+                "enum has no zero value".to_string(),
+            ),
+        }
+    }
+}
+
+// Checks every message reachable from `messages`, each one once, followed by `enums`.
+pub fn lint(messages: &[Rc<Message>], enums: &[Enum]) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        diags: Vec::new(),
+        visited: HashSet::new(),
+    };
+    for msg in messages {
+        linter.message(msg);
+    }
+    for e in enums {
+        linter.enumeration(e);
+    }
+    linter.diags
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trans::tests::*;
+
+    use super::*;
+
+    fn summary(diags: &[Diagnostic]) -> Vec<String> {
+        diags.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        let e = Enum {
+            name: "pbmsg.Color".to_string(),
+            values: vec![EnumValue {
+                name: "COLOR_UNSPECIFIED".to_string(),
+                number: 0,
+            }],
+            reserved: Vec::new(),
+        };
+        assert!(lint(&[Rc::new(get_msg_foo_type())], &[e]).is_empty());
+    }
+
+    #[test]
+    fn test_lint_tags() {
+        let msg = Message::new(
+            "pbmsg.Tags".to_string(),
+            vec![
+                field("a", 1, Kind::Int32, false),
+                field("b", 4, Kind::Int32, false),
+                field("c", 6, Kind::Int32, false),
+                field("d", 9, Kind::Int32, false),
+                field("e", 9, Kind::Int32, false),
+            ],
+            true,
+        )
+        .with_reserved(vec![2..4, 6..7, 8..9]);
+        assert_eq!(
+            summary(&lint(&[Rc::new(msg)], &[])),
+            vec![
+                "pbmsg.Tags.c: tag-reuse: tag 6 is reserved",
+                "pbmsg.Tags.c: tag-gap: tags 5..6 are neither used nor reserved",
+                "pbmsg.Tags.d: tag-gap: tags 7..8 are neither used nor reserved",
+                "pbmsg.Tags.e: tag-reuse: tag 9 is already used by 'd'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_names_and_kinds() {
+        let entry = Rc::new(Message::new(
+            "pbmsg.Names.MEntry".to_string(),
+            vec![
+                field("key", 1, get_msg_elem_type(), false),
+                field("value", 2, Kind::String, false),
+            ],
+            true,
+        ));
+        let msg = Message::new(
+            "pbmsg.Names".to_string(),
+            vec![
+                field("user_name", 1, Kind::String, false),
+                field("userName", 2, Kind::String, false),
+                field("m", 3, Kind::Map(entry), false),
+            ],
+            true,
+        );
+        let enums = [Enum {
+            name: "pbmsg.Color".to_string(),
+            values: vec![
+                EnumValue {
+                    name: "RED".to_string(),
+                    number: 0,
+                },
+                EnumValue {
+                    name: "BLUE".to_string(),
+                    number: 3,
+                },
+            ],
+            reserved: vec![2..=3],
+        }];
+        let diags = lint(&[Rc::new(msg)], &enums);
+        assert_eq!(
+            summary(&diags),
+            vec![
+                "pbmsg.Names.userName: field-name-case: field name 'userName' is not snake_case",
+                "pbmsg.Names.userName: json-name-collision: json name 'userName' collides with field 'user_name'",
+                "pbmsg.Names.m: map-message-key: map key must be a scalar type",
+                "pbmsg.Color.BLUE: tag-reuse: number 3 is reserved",
+                "pbmsg.Color.RED: enum-zero-value: zero value should be named *_UNSPECIFIED",
+            ]
+        );
+
+        let mut buf = Vec::new();
+        diags[2].write_into(&mut buf);
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            r#"{"rule":"map-message-key","path":"pbmsg.Names.m","message":"map key must be a scalar type"}"#
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
 use std::rc::Rc;
 
 // https://protobuf.dev/programming-guides/proto3/
@@ -30,6 +31,8 @@ pub struct Message {
     fields: Vec<Field>,
     tags: Vec<isize>,
     field_names: Option<HashMap<String, usize>>,
+    has_required: bool,
+    reserved: Vec<Range<u32>>,
 }
 
 #[derive(Clone)]
@@ -38,6 +41,34 @@ pub struct Field {
     pub tag: u32,
     pub kind: Kind,
     pub repeated: bool,
+    pub required: bool,
+}
+
+#[derive(Clone)]
+pub struct EnumValue {
+    pub name: String,
+    pub number: i32,
+}
+
+#[derive(Clone)]
+pub struct Enum {
+    pub name: String,
+    pub values: Vec<EnumValue>,
+    // Unlike message ranges, enum reserved ranges include their end.
+    pub reserved: Vec<RangeInclusive<i32>>,
+}
+
+#[derive(Clone)]
+pub struct Method {
+    pub name: String,
+    pub input: Rc<Message>,
+    pub output: Rc<Message>,
+}
+
+#[derive(Clone)]
+pub struct Service {
+    pub name: String,
+    pub methods: Vec<Method>,
 }
 
 impl Message {
@@ -61,14 +92,29 @@ impl Message {
                 .map(|x| (x.1.name.clone(), x.0))
                 .collect::<HashMap<_, _>>()
         });
+        let has_required = fields.iter().any(|f| {
+            f.required
+                || match f.kind {
+                    Kind::Message(ref m) => m.has_required(),
+                    Kind::Map(ref entry) => entry.has_required(),
+                    _ => false,
+                }
+        });
         Self {
             name,
             fields,
             tags,
             field_names,
+            has_required,
+            reserved: Vec::new(),
         }
     }
 
+    pub fn with_reserved(mut self, reserved: Vec<Range<u32>>) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
     pub fn get_name(&self) -> &str {
         self.name.as_str()
     }
@@ -77,6 +123,14 @@ impl Message {
         self.fields.as_slice()
     }
 
+    pub fn get_reserved(&self) -> &[Range<u32>] {
+        self.reserved.as_slice()
+    }
+
+    pub fn has_required(&self) -> bool {
+        self.has_required
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<&Field> {
         if let Some(ref m) = self.field_names {
             m.get(name).map(|&idx| &self.fields[idx])
@@ -0,0 +1,566 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::json::Value;
+use crate::metadata::*;
+use crate::{BytesEncoding, ProtoToJsonOptions};
+
+// https://spec.openapis.org/oas/v3.1.0
+
+const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn object(members: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        members
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+fn typed(ty: &str, format: &str) -> Value {
+    object(vec![("type", string(ty)), ("format", string(format))])
+}
+
+fn nullable(schema: Value) -> Value {
+    object(vec![(
+        "anyOf",
+        Value::Array(vec![schema, object(vec![("type", string("null"))])]),
+    )])
+}
+
+fn schema_ref(name: &str) -> Value {
+    object(vec![(
+        "$ref",
+        string(&format!("{}{}", SCHEMA_REF_PREFIX, name)),
+    )])
+}
+
+struct Generator<'a> {
+    schemas: BTreeMap<String, Value>,
+    pending: Vec<Rc<Message>>,
+    opts: &'a ProtoToJsonOptions,
+}
+
+impl Generator<'_> {
+    fn message_ref(&mut self, msg: &Rc<Message>) -> Value {
+        let name = msg.get_name();
+        if !self.schemas.contains_key(name) && !self.pending.iter().any(|m| m.get_name() == name) {
+            self.pending.push(msg.clone());
+        }
+        schema_ref(name)
+    }
+
+    fn int64_typed(&self, format: &str) -> Value {
+        if self.opts.quote_int64 || self.opts.canonical {
+            typed("string", format)
+        } else {
+            typed("integer", format)
+        }
+    }
+
+    // Non-finite values are written as strings unless `reject_non_finite` is set.
+    fn float_typed(&self, format: &str) -> Value {
+        if self.opts.reject_non_finite {
+            return typed("number", format);
+        }
+        let non_finite = ["NaN", "Infinity", "-Infinity"];
+        object(vec![(
+            "oneOf",
+            Value::Array(vec![
+                typed("number", format),
+                object(vec![
+                    ("type", string("string")),
+                    ("enum", Value::Array(non_finite.map(string).to_vec())),
+                ]),
+            ]),
+        )])
+    }
+
+    fn kind_schema(&mut self, kind: &Kind) -> Value {
+        match kind {
+            Kind::Double => self.float_typed("double"),
+            Kind::Float => self.float_typed("float"),
+            Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => typed("integer", "int32"),
+            Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => self.int64_typed("int64"),
+            Kind::Uint32 | Kind::Fixed32 => typed("integer", "uint32"),
+            Kind::Uint64 | Kind::Fixed64 => self.int64_typed("uint64"),
+            Kind::Bool => object(vec![("type", string("boolean"))]),
+            Kind::String => object(vec![("type", string("string"))]),
+            Kind::Bytes => {
+                let encoding = match self.opts.bytes_encoding {
+                    BytesEncoding::Standard | BytesEncoding::StandardNoPad => "base64",
+                    BytesEncoding::UrlSafe | BytesEncoding::UrlSafeNoPad => "base64url",
+                    BytesEncoding::Hex => "base16",
+                };
+                object(vec![
+                    ("type", string("string")),
+                    ("contentEncoding", string(encoding)),
+                ])
+            }
+            Kind::Message(msg) => {
+                let name = msg.get_name();
+                if let Some(conv) = self.opts.converters.lookup(name, self.opts.google_types) {
+                    return conv.json_schema().unwrap_or_else(|| object(vec![]));
+                }
+                match name {
+                    "google.protobuf.Timestamp" => typed("string", "date-time"),
+                    "google.protobuf.Duration" => object(vec![
+                        ("type", string("string")),
+                        ("pattern", string(r"^-?[0-9]+(\.[0-9]{1,9})?s$")),
+                    ]),
+                    "google.protobuf.Any" => object(vec![
+                        ("type", string("object")),
+                        (
+                            "properties",
+                            object(vec![("@type", object(vec![("type", string("string"))]))]),
+                        ),
+                        ("required", Value::Array(vec![string("@type")])),
+                    ]),
+                    "google.protobuf.Struct" => object(vec![("type", string("object"))]),
+                    "google.protobuf.ListValue" => object(vec![("type", string("array"))]),
+                    "google.protobuf.Value" => object(vec![]),
+                    "google.protobuf.FieldMask" => object(vec![("type", string("string"))]),
+                    "google.protobuf.Empty" => object(vec![("type", string("object"))]),
+                    "google.protobuf.DoubleValue" => self.kind_schema(&Kind::Double),
+                    "google.protobuf.FloatValue" => self.kind_schema(&Kind::Float),
+                    "google.protobuf.Int64Value" => self.kind_schema(&Kind::Int64),
+                    "google.protobuf.UInt64Value" => self.kind_schema(&Kind::Uint64),
+                    "google.protobuf.Int32Value" => self.kind_schema(&Kind::Int32),
+                    "google.protobuf.UInt32Value" => self.kind_schema(&Kind::Uint32),
+                    "google.protobuf.BoolValue" => self.kind_schema(&Kind::Bool),
+                    "google.protobuf.StringValue" => self.kind_schema(&Kind::String),
+                    "google.protobuf.BytesValue" => self.kind_schema(&Kind::Bytes),
+                    _ => self.message_ref(msg),
+                }
+            }
+            Kind::Map(entry) => {
+                let v_field = &entry.get_fields()[1];
+                // ptoj writes null for an entry without a message value
+                let value = match v_field.kind {
+                    Kind::Message(_) => nullable(self.kind_schema(&v_field.kind)),
+                    _ => self.field_schema(v_field),
+                };
+                object(vec![
+                    ("type", string("object")),
+                    ("additionalProperties", value),
+                ])
+            }
+        }
+    }
+
+    fn field_schema(&mut self, field: &Field) -> Value {
+        let schema = self.kind_schema(&field.kind);
+        if field.repeated {
+            object(vec![("type", string("array")), ("items", schema)])
+        } else if self.opts.emit_unpopulated && matches!(field.kind, Kind::Message(_)) {
+            // unset message fields are written as null
+            nullable(schema)
+        } else {
+            schema
+        }
+    }
+
+    fn message_schema(&mut self, msg: &Message) -> Value {
+        let mut props = Vec::with_capacity(msg.get_fields().len());
+        let mut required = Vec::new();
+        for field in msg.get_fields() {
+            props.push((field.name.clone(), self.field_schema(field)));
+            if field.required {
+                required.push(string(&field.name));
+            }
+        }
+        let mut members = vec![
+            ("type", string("object")),
+            ("properties", Value::Object(props)),
+        ];
+        if !required.is_empty() {
+            members.push(("required", Value::Array(required)));
+        }
+        object(members)
+    }
+
+    fn flush(&mut self) {
+        while let Some(msg) = self.pending.pop() {
+            let schema = self.message_schema(&msg);
+            self.schemas.insert(msg.get_name().to_string(), schema);
+        }
+    }
+}
+
+fn json_content(schema: Value) -> Value {
+    object(vec![("application/json", object(vec![("schema", schema)]))])
+}
+
+// Each method is exposed as `POST /{service}/{method}`, and every message reachable from a
+// method is written to `components/schemas` under its full name. The schemas describe the
+// JSON that `trans_proto_to_json_with` writes with `opts`.
+pub fn gen_openapi(
+    buf: &mut Vec<u8>,
+    title: &str,
+    version: &str,
+    services: &[Service],
+    opts: &ProtoToJsonOptions,
+) {
+    let mut gen = Generator {
+        schemas: BTreeMap::new(),
+        pending: Vec::new(),
+        opts,
+    };
+    let mut paths = Vec::new();
+    for svc in services {
+        for method in &svc.methods {
+            let op = object(vec![
+                (
+                    "operationId",
+                    string(&format!("{}.{}", svc.name, method.name)),
+                ),
+                ("tags", Value::Array(vec![string(&svc.name)])),
+                (
+                    "requestBody",
+                    object(vec![
+                        ("required", Value::Bool(true)),
+                        ("content", json_content(gen.message_ref(&method.input))),
+                    ]),
+                ),
+                (
+                    "responses",
+                    object(vec![(
+                        "200",
+                        object(vec![
+                            ("description", string("OK")),
+                            ("content", json_content(gen.message_ref(&method.output))),
+                        ]),
+                    )]),
+                ),
+            ]);
+            paths.push((
+                format!("/{}/{}", svc.name, method.name),
+                object(vec![("post", op)]),
+            ));
+            gen.flush();
+        }
+    }
+
+    let doc = object(vec![
+        ("openapi", string("3.1.0")),
+        (
+            "info",
+            object(vec![("title", string(title)), ("version", string(version))]),
+        ),
+        ("paths", Value::Object(paths)),
+        (
+            "components",
+            object(vec![(
+                "schemas",
+                Value::Object(gen.schemas.into_iter().collect()),
+            )]),
+        ),
+    ]);
+    doc.write_into(buf);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{parse_value, Iter, Token};
+    use crate::proto::{Decoder, Encoder};
+    use crate::trans::tests::*;
+    use crate::{Converter, Converters, JsonToProtoOptions, Result};
+
+    use super::*;
+
+    fn lookup<'a>(v: &'a Value, path: &[&str]) -> &'a Value {
+        path.iter().fold(v, |v, k| {
+            v.get(k).unwrap_or_else(|| panic!("missing {}", k))
+        })
+    }
+
+    #[test]
+    fn test_gen_openapi() {
+        let foo = Rc::new(get_msg_foo_type());
+        let services = vec![Service {
+            name: "pbmsg.FooService".to_string(),
+            methods: vec![Method {
+                name: "Echo".to_string(),
+                input: foo.clone(),
+                output: foo,
+            }],
+        }];
+        let mut buf = Vec::new();
+        gen_openapi(
+            &mut buf,
+            "foo",
+            "1.0.0",
+            &services,
+            &ProtoToJsonOptions::default(),
+        );
+        let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+
+        let op = lookup(&doc, &["paths", "/pbmsg.FooService/Echo", "post"]);
+        assert_eq!(
+            lookup(
+                op,
+                &[
+                    "requestBody",
+                    "content",
+                    "application/json",
+                    "schema",
+                    "$ref"
+                ]
+            ),
+            &string("#/components/schemas/pbmsg.Foo")
+        );
+        let schemas = lookup(&doc, &["components", "schemas"]);
+        match schemas {
+            Value::Object(members) => assert_eq!(
+                members.iter().map(|m| m.0.as_str()).collect::<Vec<_>>(),
+                vec!["pbmsg.Elem", "pbmsg.Foo", "pbmsg.Foo.Embed"]
+            ),
+            _ => panic!("expect object"),
+        }
+        let props = lookup(schemas, &["pbmsg.Foo", "properties"]);
+        assert_eq!(lookup(props, &["c", "format"]), &string("int32"));
+        assert_eq!(lookup(props, &["e", "type"]), &string("array"));
+        assert_eq!(
+            lookup(props, &["g", "items", "$ref"]),
+            &string("#/components/schemas/pbmsg.Elem")
+        );
+    }
+
+    struct Opaque;
+
+    impl Converter for Opaque {
+        fn to_json(&self, _: &mut Vec<u8>, _: &mut Decoder, _: &ProtoToJsonOptions) -> Result<()> {
+            Ok(())
+        }
+
+        fn to_proto(
+            &self,
+            _: &mut Encoder,
+            _: &mut Iter,
+            _: Token,
+            _: &JsonToProtoOptions,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_gen_openapi_converters() {
+        let named =
+            |name: &str| Kind::Message(Rc::new(Message::new(name.to_string(), vec![], true)));
+        let msg = Rc::new(Message::new(
+            "pbmsg.Converted".to_string(),
+            vec![
+                field("d", 1, named("google.type.Date"), false),
+                field("o", 2, named("pbmsg.Opaque"), false),
+            ],
+            true,
+        ));
+        let services = vec![Service {
+            name: "pbmsg.Svc".to_string(),
+            methods: vec![Method {
+                name: "Get".to_string(),
+                input: msg.clone(),
+                output: msg,
+            }],
+        }];
+        let mut converters = Converters::new();
+        converters.register("pbmsg.Opaque", Rc::new(Opaque));
+        let gen = |opts: &ProtoToJsonOptions| {
+            let mut buf = Vec::new();
+            gen_openapi(&mut buf, "converters", "1", &services, opts);
+            let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+            let mut z = Vec::new();
+            lookup(
+                &doc,
+                &["components", "schemas", "pbmsg.Converted", "properties"],
+            )
+            .write_into(&mut z);
+            String::from_utf8(z).unwrap()
+        };
+
+        assert_eq!(
+            gen(&ProtoToJsonOptions::default()),
+            concat!(
+                r##"{"d":{"$ref":"#\/components\/schemas\/google.type.Date"},"##,
+                r##""o":{"$ref":"#\/components\/schemas\/pbmsg.Opaque"}}"##
+            )
+        );
+        let opts = ProtoToJsonOptions {
+            google_types: true,
+            converters,
+            ..Default::default()
+        };
+        assert_eq!(
+            gen(&opts),
+            r#"{"d":{"type":"string","format":"date"},"o":{}}"#
+        );
+    }
+
+    #[test]
+    fn test_gen_openapi_kinds() {
+        let elem = match get_msg_elem_type() {
+            Kind::Message(m) => m,
+            _ => unreachable!(),
+        };
+        let wkt = |name: &str| Kind::Message(Rc::new(Message::new(name.to_string(), vec![], true)));
+        let entry = Rc::new(Message::new(
+            "pbmsg.Kinds.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Message(elem), false),
+            ],
+            true,
+        ));
+        let msg = Rc::new(Message::new(
+            "pbmsg.Kinds".to_string(),
+            vec![
+                field("i", 1, Kind::Sint64, false),
+                field("u", 2, Kind::Fixed64, false),
+                field("b", 3, Kind::Bytes, false),
+                field("m", 4, Kind::Map(entry), false),
+                field("t", 5, wkt("google.protobuf.Timestamp"), false),
+                field("d", 6, wkt("google.protobuf.Duration"), false),
+                field("a", 7, wkt("google.protobuf.Any"), false),
+                field("s", 8, wkt("google.protobuf.Struct"), false),
+                field("l", 9, wkt("google.protobuf.ListValue"), false),
+                field("v", 10, wkt("google.protobuf.Value"), false),
+                field("e", 11, wkt("google.protobuf.Empty"), false),
+                field("w", 12, wkt("google.protobuf.UInt32Value"), false),
+                field("f", 13, wkt("google.protobuf.FieldMask"), false),
+                field("r", 14, Kind::Float, false),
+            ],
+            true,
+        ));
+        let services = vec![Service {
+            name: "pbmsg.Svc".to_string(),
+            methods: vec![Method {
+                name: "Get".to_string(),
+                input: msg.clone(),
+                output: msg,
+            }],
+        }];
+        let mut buf = Vec::new();
+        gen_openapi(
+            &mut buf,
+            "kinds",
+            "1",
+            &services,
+            &ProtoToJsonOptions::default(),
+        );
+        let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+        let props = lookup(
+            &doc,
+            &["components", "schemas", "pbmsg.Kinds", "properties"],
+        );
+
+        let mut z = Vec::new();
+        props.write_into(&mut z);
+        assert_eq!(
+            std::str::from_utf8(&z).unwrap(),
+            concat!(
+                r#"{"i":{"type":"integer","format":"int64"},"#,
+                r#""u":{"type":"integer","format":"uint64"},"#,
+                r#""b":{"type":"string","contentEncoding":"base64"},"#,
+                r#""m":{"type":"object","additionalProperties":{"anyOf":"#,
+                r##"[{"$ref":"#\/components\/schemas\/pbmsg.Elem"},{"type":"null"}]}},"##,
+                r#""t":{"type":"string","format":"date-time"},"#,
+                r#""d":{"type":"string","pattern":"^-?[0-9]+(\\.[0-9]{1,9})?s$"},"#,
+                r#""a":{"type":"object","properties":{"@type":{"type":"string"}},"required":["@type"]},"#,
+                r#""s":{"type":"object"},"l":{"type":"array"},"v":{},"#,
+                r#""e":{"type":"object"},"w":{"type":"integer","format":"uint32"},"#,
+                r#""f":{"type":"string"},"#,
+                r#""r":{"oneOf":[{"type":"number","format":"float"},"#,
+                r#"{"type":"string","enum":["NaN","Infinity","-Infinity"]}]}}"#
+            )
+        );
+
+        let opts = ProtoToJsonOptions {
+            emit_unpopulated: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        gen_openapi(&mut buf, "kinds", "1", &services, &opts);
+        let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+        let props = lookup(
+            &doc,
+            &["components", "schemas", "pbmsg.Kinds", "properties"],
+        );
+        let mut z = Vec::new();
+        lookup(props, &["t"]).write_into(&mut z);
+        assert_eq!(
+            std::str::from_utf8(&z).unwrap(),
+            r#"{"anyOf":[{"type":"string","format":"date-time"},{"type":"null"}]}"#
+        );
+        assert_eq!(lookup(props, &["i", "format"]), &string("int64"));
+
+        for opts in [
+            ProtoToJsonOptions::spec(),
+            ProtoToJsonOptions {
+                canonical: true,
+                ..Default::default()
+            },
+        ] {
+            let mut buf = Vec::new();
+            gen_openapi(&mut buf, "kinds", "1", &services, &opts);
+            let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+            let props = lookup(
+                &doc,
+                &["components", "schemas", "pbmsg.Kinds", "properties"],
+            );
+            assert_eq!(lookup(props, &["i", "type"]), &string("string"));
+            assert_eq!(lookup(props, &["u", "type"]), &string("string"));
+            assert_eq!(lookup(props, &["u", "format"]), &string("uint64"));
+        }
+
+        let opts = ProtoToJsonOptions {
+            reject_non_finite: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        gen_openapi(&mut buf, "kinds", "1", &services, &opts);
+        let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+        let props = lookup(
+            &doc,
+            &["components", "schemas", "pbmsg.Kinds", "properties"],
+        );
+        let mut z = Vec::new();
+        lookup(props, &["r"]).write_into(&mut z);
+        assert_eq!(
+            std::str::from_utf8(&z).unwrap(),
+            r#"{"type":"number","format":"float"}"#
+        );
+
+        for (bytes_encoding, encoding) in [
+            (BytesEncoding::UrlSafeNoPad, "base64url"),
+            (BytesEncoding::Hex, "base16"),
+        ] {
+            let opts = ProtoToJsonOptions {
+                bytes_encoding,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            gen_openapi(&mut buf, "kinds", "1", &services, &opts);
+            let doc = parse_value(&mut Iter::new(&buf)).unwrap();
+            assert_eq!(
+                lookup(
+                    &doc,
+                    &[
+                        "components",
+                        "schemas",
+                        "pbmsg.Kinds",
+                        "properties",
+                        "b",
+                        "contentEncoding"
+                    ]
+                ),
+                &string(encoding)
+            );
+        }
+    }
+}
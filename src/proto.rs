@@ -43,6 +43,12 @@ pub struct Encoder {
     inner: Vec<u8>,
 }
 
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Encoder {
     pub const fn from_vec(buf: Vec<u8>) -> Self {
         Self { inner: buf }
@@ -92,7 +98,8 @@ impl Encoder {
                     }
                 }
                 p.add(n).write(v as u8);
-                self.inner.extend_from_slice(&(*buf.as_ptr())[..n + 1]);
+                self.inner
+                    .extend_from_slice(std::slice::from_raw_parts(p, n + 1));
             }
         } else {
             self.inner.push(v as u8);
@@ -189,7 +196,7 @@ pub struct Decoder<'a> {
     p: usize,
 }
 
-impl Decoder<'_> {
+impl<'a> Decoder<'a> {
     pub fn eof(&self) -> bool {
         self.s.len() == self.p
     }
@@ -244,7 +251,7 @@ impl Decoder<'_> {
         self.read_fixed::<u64>()
     }
 
-    pub fn read_data(&mut self) -> io::Result<&[u8]> {
+    pub fn read_data(&mut self) -> io::Result<&'a [u8]> {
         self.read_varint().map(|x| x as usize).and_then(|n| {
             if self.p + n <= self.s.len() {
                 let data = &self.s[self.p..self.p + n];
@@ -261,6 +268,10 @@ impl<'a> Decoder<'a> {
     pub fn new(s: &'a [u8]) -> Self {
         Self { s, p: 0 }
     }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.s[self.p..]
+    }
 }
 
 impl<'a> From<&'a [u8]> for Decoder<'a> {
@@ -1,14 +1,27 @@
+use std::borrow::Cow;
 use std::error;
 use std::fmt;
 use std::io;
+use std::rc::Rc;
 use std::result;
 
+use crate::metadata::Message;
+
 mod append;
+mod check;
+mod convert;
+mod gtype;
 mod jtop;
+mod number;
 mod ptoj;
+mod wkt;
 
-pub use jtop::trans_json_to_proto;
-pub use ptoj::trans_proto_to_json;
+pub use check::check_initialized;
+pub use convert::{Converter, Converters};
+pub use jtop::{
+    trans_json_to_proto, trans_json_to_proto_with, JsonToProtoOptions, UnknownFieldHandler,
+};
+pub use ptoj::{trans_proto_to_json, trans_proto_to_json_with, BytesEncoding, ProtoToJsonOptions};
 
 #[derive(Debug)]
 pub enum Error {
@@ -16,6 +29,9 @@ pub enum Error {
     UnexpectedToken,
     TypeMismatch,
     InvalidWireType,
+    MissingRequired(Vec<String>),
+    UnknownField(String),
+    DuplicateKey(String),
     Io(io::Error),
     Wrap(Box<dyn error::Error>),
 }
@@ -27,6 +43,11 @@ impl fmt::Display for Error {
             Error::UnexpectedToken => f.write_str("unexpected token"),
             Error::TypeMismatch => f.write_str("type mismatch"),
             Error::InvalidWireType => f.write_str("invalid wire-type"),
+            Error::MissingRequired(paths) => {
+                write!(f, "missing required fields: {}", paths.join(", "))
+            }
+            Error::UnknownField(path) => write!(f, "unknown field: {}", path),
+            Error::DuplicateKey(path) => write!(f, "duplicate key: {}", path),
             Error::Io(e) => write!(f, "io: {}", e),
             Error::Wrap(e) => write!(f, "wrap: {}", e),
         }
@@ -43,11 +64,42 @@ impl From<io::Error> for Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+// Looks up the message type named by the type URL of a `google.protobuf.Any`.
+pub trait TypeResolver {
+    fn resolve(&self, type_url: &str) -> Option<Rc<Message>>;
+}
+
+// What to do with string fields that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    #[default]
+    Reject,
+    Replace,
+    Pass,
+}
+
+fn check_utf8<'a>(s: &'a [u8], field: &str, policy: Utf8Policy) -> Result<Cow<'a, [u8]>> {
+    match std::str::from_utf8(s) {
+        Ok(_) => Ok(Cow::Borrowed(s)),
+        Err(_) if policy == Utf8Policy::Pass => Ok(Cow::Borrowed(s)),
+        Err(_) if policy == Utf8Policy::Replace => Ok(Cow::Owned(
+            String::from_utf8_lossy(s).into_owned().into_bytes(),
+        )),
+        Err(e) => Err(Error::Wrap(
+            format!("invalid utf-8 in field '{}': {}", field, e).into(),
+        )),
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::rc::Rc;
 
+    use crate::json::Iter;
     use crate::metadata::{Field, Kind, Message};
+    use crate::proto::{Decoder, Encoder};
+
+    use super::*;
 
     pub fn printable(s: &[u8]) -> String {
         s.iter()
@@ -56,6 +108,91 @@ pub mod tests {
             .join(" ")
     }
 
+    pub fn field(name: &str, tag: u32, kind: Kind, repeated: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            tag,
+            kind,
+            repeated,
+            required: false,
+        }
+    }
+
+    pub fn message_type(name: &str, fields: Vec<Field>) -> Kind {
+        Kind::Message(Rc::new(Message::new(name.to_string(), fields, false)))
+    }
+
+    // A message with a singular, a repeated and a map field of `kind`.
+    pub fn holder(kind: Kind) -> Message {
+        let entry = Message::new(
+            "pbmsg.Holder.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, kind.clone(), false),
+            ],
+            false,
+        );
+        Message::new(
+            "pbmsg.Holder".to_string(),
+            vec![
+                field("v", 1, kind.clone(), false),
+                field("r", 2, kind, true),
+                field("m", 3, Kind::Map(Rc::new(entry)), false),
+            ],
+            false,
+        )
+    }
+
+    pub fn json_to_proto_with(
+        s: &str,
+        msg: &Message,
+        opts: &JsonToProtoOptions,
+    ) -> Result<Vec<u8>> {
+        let mut enc = Encoder::new();
+        trans_json_to_proto_with(&mut enc, &mut Iter::new(s.as_bytes()), msg, opts)?;
+        Ok(enc.into_inner())
+    }
+
+    pub fn proto_to_json_with(
+        data: &[u8],
+        msg: &Message,
+        opts: &ProtoToJsonOptions,
+    ) -> Result<String> {
+        let mut buf = Vec::new();
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(data), msg, opts)?;
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    pub fn round_trip_with(
+        s: &str,
+        msg: &Message,
+        to_proto: &JsonToProtoOptions,
+        to_json: &ProtoToJsonOptions,
+    ) -> Result<String> {
+        proto_to_json_with(&json_to_proto_with(s, msg, to_proto)?, msg, to_json)
+    }
+
+    // The helpers without options translate Google types and leave the rest at the defaults.
+    pub fn json_to_proto(s: &str, msg: &Message) -> Result<Vec<u8>> {
+        let opts = JsonToProtoOptions {
+            google_types: true,
+            ..Default::default()
+        };
+        json_to_proto_with(s, msg, &opts)
+    }
+
+    pub fn proto_to_json(data: &[u8], msg: &Message) -> Result<String> {
+        let opts = ProtoToJsonOptions {
+            google_types: true,
+            ..Default::default()
+        };
+        proto_to_json_with(data, msg, &opts)
+    }
+
+    pub fn round_trip(s: &str, msg: &Message) -> Result<String> {
+        proto_to_json(&json_to_proto(s, msg)?, msg)
+    }
+
     pub fn get_msg_elem_type() -> Kind {
         Kind::Message(Rc::new(Message::new(
             "pbmsg.Elem".to_string(),
@@ -65,12 +202,14 @@ pub mod tests {
                     tag: 1,
                     kind: Kind::Int32,
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "s".to_string(),
                     tag: 2,
                     kind: Kind::String,
                     repeated: false,
+                    required: false,
                 },
             ],
             true,
@@ -86,18 +225,89 @@ pub mod tests {
                     tag: 1,
                     kind: Kind::Int32,
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "b".to_string(),
                     tag: 2,
                     kind: Kind::String,
                     repeated: false,
+                    required: false,
                 },
             ],
             true,
         )))
     }
 
+    pub fn get_msg_floats_type() -> Message {
+        let entry = Message::new(
+            "pbmsg.Floats.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Double, false),
+            ],
+            true,
+        );
+        Message::new(
+            "pbmsg.Floats".to_string(),
+            vec![
+                field("d", 1, Kind::Double, false),
+                field("f", 2, Kind::Float, true),
+                field("m", 3, Kind::Map(Rc::new(entry)), false),
+            ],
+            true,
+        )
+    }
+
+    // {"d":"NaN","f":["Infinity",1.5,"-Infinity"],"m":{"a":"-Infinity"}}
+    pub fn get_floats_non_finite() -> Vec<u8> {
+        let mut entry = Encoder::new();
+        entry.emit_len_delim(1, b"a");
+        entry.emit_u64(2, f64::NEG_INFINITY.to_bits());
+        let mut f = Vec::new();
+        for v in [f32::INFINITY, 1.5, f32::NEG_INFINITY] {
+            f.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut enc = Encoder::new();
+        enc.emit_u64(1, f64::NAN.to_bits());
+        enc.emit_len_delim(2, &f);
+        enc.emit_len_delim(3, entry.as_bytes());
+        enc.into_inner()
+    }
+
+    pub fn get_msg_blobs_type() -> Message {
+        let entry = Message::new(
+            "pbmsg.Blobs.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Bytes, false),
+            ],
+            true,
+        );
+        Message::new(
+            "pbmsg.Blobs".to_string(),
+            vec![
+                field("b", 1, Kind::Bytes, false),
+                field("r", 2, Kind::Bytes, true),
+                field("m", 3, Kind::Map(Rc::new(entry)), false),
+            ],
+            true,
+        )
+    }
+
+    // {"b":"+/8=","r":["+/8=","+/8="],"m":{"a":"+/8="}}
+    pub fn get_blobs() -> Vec<u8> {
+        let mut entry = Encoder::new();
+        entry.emit_len_delim(1, b"a");
+        entry.emit_len_delim(2, &[0xfb, 0xff]);
+        let mut enc = Encoder::new();
+        enc.emit_len_delim(1, &[0xfb, 0xff]);
+        enc.emit_len_delim(2, &[0xfb, 0xff]);
+        enc.emit_len_delim(2, &[0xfb, 0xff]);
+        enc.emit_len_delim(3, entry.as_bytes());
+        enc.into_inner()
+    }
+
     pub fn get_msg_foo_type() -> Message {
         Message::new(
             "pbmsg.Foo".to_string(),
@@ -107,42 +317,49 @@ pub mod tests {
                     tag: 1,
                     kind: Kind::String,
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "b".to_string(),
                     tag: 2,
                     kind: Kind::Bool,
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "c".to_string(),
                     tag: 3,
                     kind: Kind::Int32,
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "d".to_string(),
                     tag: 4,
                     kind: get_msg_foo_embed_type(),
                     repeated: false,
+                    required: false,
                 },
                 Field {
                     name: "e".to_string(),
                     tag: 5,
                     kind: Kind::Int32,
                     repeated: true,
+                    required: false,
                 },
                 Field {
                     name: "f".to_string(),
                     tag: 6,
                     kind: Kind::String,
                     repeated: true,
+                    required: false,
                 },
                 Field {
                     name: "g".to_string(),
                     tag: 7,
                     kind: get_msg_elem_type(),
                     repeated: true,
+                    required: false,
                 },
             ],
             true,
@@ -1,21 +1,48 @@
 pub trait Append {
     fn append_into(self, buf: &mut Vec<u8>);
+
+    fn is_finite(&self) -> bool {
+        true
+    }
+}
+
+// https://protobuf.dev/programming-guides/json/
+fn append_non_finite(buf: &mut Vec<u8>, nan: bool, negative: bool) {
+    buf.extend_from_slice(match (nan, negative) {
+        (true, _) => b"\"NaN\"",
+        (false, false) => b"\"Infinity\"",
+        (false, true) => b"\"-Infinity\"",
+    });
 }
 
 impl Append for f32 {
     fn append_into(self, buf: &mut Vec<u8>) {
+        if !f32::is_finite(self) {
+            return append_non_finite(buf, self.is_nan(), self.is_sign_negative());
+        }
         let mut b = dtoa::Buffer::new();
         let printed = b.format(self);
         buf.extend_from_slice(printed.as_bytes());
     }
+
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
 }
 
 impl Append for f64 {
     fn append_into(self, buf: &mut Vec<u8>) {
+        if !f64::is_finite(self) {
+            return append_non_finite(buf, self.is_nan(), self.is_sign_negative());
+        }
         let mut b = dtoa::Buffer::new();
         let printed = b.format(self);
         buf.extend_from_slice(printed.as_bytes());
     }
+
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
 }
 
 impl Append for bool {
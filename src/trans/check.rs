@@ -0,0 +1,265 @@
+use crate::metadata::*;
+use crate::proto::*;
+
+use super::*;
+
+fn read_value<'a>(dec: &mut Decoder<'a>, wire: u32) -> Result<Option<&'a [u8]>> {
+    match wire {
+        WIRE_VARINT => dec.read_varint().map(|_| None).map_err(Error::from),
+        WIRE_32BIT => dec.read_32bit().map(|_| None).map_err(Error::from),
+        WIRE_64BIT => dec.read_64bit().map(|_| None).map_err(Error::from),
+        WIRE_LEN_DELIM => dec.read_data().map(Some).map_err(Error::from),
+        _ => Err(Error::InvalidWireType),
+    }
+}
+
+fn map_key_path<'a>(path: &mut String, data: &'a [u8]) -> Result<Option<&'a [u8]>> {
+    let mut dec = Decoder::new(data);
+    let mut key = None;
+    let mut val = None;
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        let v = read_value(&mut dec, wire)?;
+        match tag {
+            1 => key = v,
+            2 => val = v,
+            _ => {}
+        }
+    }
+    path.push('[');
+    path.push_str(&String::from_utf8_lossy(key.unwrap_or_default()));
+    path.push(']');
+    Ok(val)
+}
+
+fn check_message(
+    dec: &mut Decoder,
+    msg: &Message,
+    path: &mut String,
+    missing: &mut Vec<String>,
+) -> Result<()> {
+    let mut seen: Vec<u32> = Vec::new();
+    let mut counts: Vec<(u32, usize)> = Vec::new();
+    // Records of a singular message field are merged, so they are checked together at the end.
+    let mut singular: Vec<(&Field, &Message, Vec<u8>)> = Vec::new();
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        let data = read_value(dec, wire)?;
+        let field = match msg.get_by_tag(tag) {
+            Some(f) => f,
+            _ => continue,
+        };
+        if field.required && !seen.contains(&tag) {
+            seen.push(tag);
+        }
+
+        let (sub, data) = match field.kind {
+            Kind::Message(ref m) | Kind::Map(ref m) if m.has_required() => (m, data),
+            _ => continue,
+        };
+        let data = data.ok_or(Error::InvalidWireType)?;
+        if !field.repeated && matches!(field.kind, Kind::Message(_)) {
+            match singular.iter_mut().find(|x| x.0.tag == tag) {
+                Some(x) => x.2.extend_from_slice(data),
+                None => singular.push((field, sub, data.to_vec())),
+            }
+            continue;
+        }
+        let n = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(&field.name);
+        if let Kind::Map(_) = field.kind {
+            let v_field = &sub.get_fields()[1];
+            let val = map_key_path(path, data)?;
+            if let Kind::Message(ref m) = v_field.kind {
+                check_message(&mut Decoder::new(val.unwrap_or_default()), m, path, missing)?;
+            }
+        } else {
+            let idx = match counts.iter_mut().find(|x| x.0 == tag) {
+                Some(c) => {
+                    c.1 += 1;
+                    c.1
+                }
+                None => {
+                    counts.push((tag, 0));
+                    0
+                }
+            };
+            path.push('[');
+            path.push_str(&idx.to_string());
+            path.push(']');
+            check_message(&mut Decoder::new(data), sub, path, missing)?;
+        }
+        path.truncate(n);
+    }
+
+    for (field, sub, data) in singular {
+        let n = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(&field.name);
+        check_message(&mut Decoder::new(&data), sub, path, missing)?;
+        path.truncate(n);
+    }
+
+    for field in msg.get_fields() {
+        if field.required && !seen.contains(&field.tag) {
+            if path.is_empty() {
+                missing.push(field.name.clone());
+            } else {
+                missing.push(format!("{}.{}", path, field.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn check_initialized(data: &[u8], msg: &Message) -> Result<()> {
+    if !msg.has_required() {
+        return Ok(());
+    }
+    let mut missing = Vec::new();
+    check_message(
+        &mut Decoder::new(data),
+        msg,
+        &mut String::new(),
+        &mut missing,
+    )?;
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::MissingRequired(missing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::json::Iter;
+    use crate::trans::tests::*;
+
+    use super::*;
+
+    fn get_msg_required_type() -> Message {
+        let inner = Rc::new(Message::new(
+            "pbmsg.Inner".to_string(),
+            vec![
+                Field {
+                    required: true,
+                    ..field("a", 1, Kind::Int32, false)
+                },
+                field("b", 2, Kind::String, false),
+            ],
+            true,
+        ));
+        let entry = Rc::new(Message::new(
+            "pbmsg.Outer.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Message(inner.clone()), false),
+            ],
+            true,
+        ));
+        Message::new(
+            "pbmsg.Outer".to_string(),
+            vec![
+                Field {
+                    required: true,
+                    ..field("s", 1, Kind::String, false)
+                },
+                field("d", 2, Kind::Message(inner.clone()), false),
+                field("g", 3, Kind::Message(inner), true),
+                field("m", 4, Kind::Map(entry), false),
+            ],
+            true,
+        )
+    }
+
+    fn json_to_proto(s: &str, allow_partial: bool) -> Result<Vec<u8>> {
+        let mut enc = Encoder::new();
+        let opts = JsonToProtoOptions {
+            allow_partial,
+            ..Default::default()
+        };
+        trans_json_to_proto_with(
+            &mut enc,
+            &mut Iter::new(s.as_bytes()),
+            &get_msg_required_type(),
+            &opts,
+        )
+        .map(|_| enc.into_inner())
+    }
+
+    fn missing_of(r: Result<Vec<u8>>) -> Vec<String> {
+        match r {
+            Err(Error::MissingRequired(paths)) => paths,
+            _ => panic!("expect missing required fields"),
+        }
+    }
+
+    #[test]
+    fn test_check_initialized() {
+        let data = json_to_proto(
+            r#"{"s":"","d":{"a":0},"g":[{"a":1}],"m":{"k":{"a":2}}}"#,
+            false,
+        )
+        .unwrap();
+        assert!(check_initialized(&data, &get_msg_required_type()).is_ok());
+    }
+
+    #[test]
+    fn test_check_initialized_missing() {
+        let r = json_to_proto(
+            r#"{"d":{"b":"x"},"g":[{"a":1},{"b":"y"}],"m":{"k":{}}}"#,
+            false,
+        );
+        assert_eq!(missing_of(r), vec!["g[1].a", "m[k].a", "d.a", "s"]);
+    }
+
+    #[test]
+    fn test_check_initialized_merged() {
+        let required = |name: &str, tag| Field {
+            required: true,
+            ..field(name, tag, Kind::Int32, false)
+        };
+        let pair = Rc::new(Message::new(
+            "pbmsg.Pair".to_string(),
+            vec![required("a", 1), required("b", 2)],
+            true,
+        ));
+        let msg = Message::new(
+            "pbmsg.Split".to_string(),
+            vec![field("d", 1, Kind::Message(pair), false)],
+            true,
+        );
+        let mut enc = Encoder::new();
+        enc.emit_len_delim(1, &[8, 1]);
+        enc.emit_len_delim(1, &[16, 2]);
+        assert!(check_initialized(enc.as_bytes(), &msg).is_ok());
+
+        let r = check_initialized(&[10, 2, 8, 1], &msg);
+        assert!(matches!(r, Err(Error::MissingRequired(ref p)) if p == &["d.b"]));
+    }
+
+    #[test]
+    fn test_check_initialized_partial() {
+        let data = json_to_proto(r#"{"d":{}}"#, true).unwrap();
+        let msg = get_msg_required_type();
+
+        let mut buf = Vec::new();
+        let r = trans_proto_to_json(&mut buf, &mut Decoder::new(&data), &msg);
+        assert_eq!(missing_of(r.map(|_| buf)), vec!["d.a", "s"]);
+
+        let mut buf = Vec::new();
+        let opts = ProtoToJsonOptions {
+            allow_partial: true,
+            ..Default::default()
+        };
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(&data), &msg, &opts).unwrap();
+        assert_eq!(buf, br#"{"d":{}}"#);
+    }
+}
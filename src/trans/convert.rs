@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::json::{Iter, Token, Value};
+use crate::proto::*;
+
+use super::*;
+
+// Translates a message type to and from its own JSON representation. `to_json` receives the
+// encoded message, `to_proto` the first token of the JSON value and must encode the message
+// fields without a length prefix. Strings should honour the `utf8`, `escape` and
+// `lone_surrogates` options. `json_schema` describes what `to_json` writes for generated
+// documents, `None` leaves the value unconstrained.
+pub trait Converter {
+    fn to_json(
+        &self,
+        buf: &mut Vec<u8>,
+        dec: &mut Decoder,
+        opts: &ProtoToJsonOptions,
+    ) -> Result<()>;
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        it: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()>;
+    fn json_schema(&self) -> Option<Value> {
+        None
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Converters {
+    by_name: HashMap<String, Rc<dyn Converter>>,
+}
+
+impl Converters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: impl Into<String>, conv: Rc<dyn Converter>) {
+        self.by_name.insert(name.into(), conv);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Converter> {
+        self.by_name.get(name).map(|c| c.as_ref())
+    }
+
+    // Registered converters take precedence over the built-in ones.
+    pub(crate) fn lookup(&self, name: &str, google_types: bool) -> Option<&dyn Converter> {
+        match self.get(name) {
+            Some(conv) => Some(conv),
+            None if google_types => gtype::find(name),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::Message;
+    use crate::trans::tests::*;
+    use crate::trans::{JsonToProtoOptions, ProtoToJsonOptions};
+
+    use super::*;
+
+    struct Uuid;
+
+    impl Converter for Uuid {
+        fn to_json(
+            &self,
+            buf: &mut Vec<u8>,
+            dec: &mut Decoder,
+            _: &ProtoToJsonOptions,
+        ) -> Result<()> {
+            let mut value: &[u8] = &[];
+            while !dec.eof() {
+                let (tag, _) = dec.read_key().map_err(Error::from)?;
+                assert_eq!(tag, 1);
+                value = dec.read_data().map_err(Error::from)?;
+            }
+            if value.len() != 16 {
+                return Err(Error::Wrap("uuid must be 16 bytes".into()));
+            }
+            buf.push(b'"');
+            for (i, b) in value.iter().enumerate() {
+                if matches!(i, 4 | 6 | 8 | 10) {
+                    buf.push(b'-');
+                }
+                buf.extend_from_slice(format!("{:02x}", b).as_bytes());
+            }
+            buf.push(b'"');
+            Ok(())
+        }
+
+        fn to_proto(
+            &self,
+            enc: &mut Encoder,
+            _: &mut Iter,
+            lead: Token,
+            _: &JsonToProtoOptions,
+        ) -> Result<()> {
+            let s = match lead {
+                Token::String(s) => &s[1..s.len() - 1],
+                _ => return Err(Error::TypeMismatch),
+            };
+            let hex = s
+                .iter()
+                .filter(|&&c| c != b'-')
+                .copied()
+                .collect::<Vec<_>>();
+            if s.len() != 36 || hex.len() != 32 {
+                return Err(Error::Wrap("malformed uuid".into()));
+            }
+            let value = hex
+                .chunks(2)
+                .map(|c| u8::from_str_radix(std::str::from_utf8(c).unwrap(), 16))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| Error::Wrap(e.into()))?;
+            enc.emit_len_delim(1, &value);
+            Ok(())
+        }
+    }
+
+    struct Constant(&'static str);
+
+    impl Converter for Constant {
+        fn to_json(
+            &self,
+            buf: &mut Vec<u8>,
+            _: &mut Decoder,
+            _: &ProtoToJsonOptions,
+        ) -> Result<()> {
+            buf.extend_from_slice(self.0.as_bytes());
+            Ok(())
+        }
+
+        fn to_proto(
+            &self,
+            _: &mut Encoder,
+            _: &mut Iter,
+            _: Token,
+            _: &JsonToProtoOptions,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn convert_round_trip(s: &str, msg: &Message, converters: &Converters) -> Result<String> {
+        let to_proto = JsonToProtoOptions {
+            google_types: true,
+            converters: converters.clone(),
+            ..Default::default()
+        };
+        let to_json = ProtoToJsonOptions {
+            google_types: true,
+            converters: converters.clone(),
+            ..Default::default()
+        };
+        round_trip_with(s, msg, &to_proto, &to_json)
+    }
+
+    #[test]
+    fn test_converters() {
+        let mut converters = Converters::new();
+        converters.register("our.Uuid", Rc::new(Uuid));
+        assert!(converters.get("our.Uuid").is_some());
+        assert!(converters.get("our.Ip").is_none());
+
+        let msg = holder(message_type("our.Uuid", vec![]));
+        let s = concat!(
+            r#"{"v":"123e4567-e89b-12d3-a456-426614174000","#,
+            r#""r":["00000000-0000-0000-0000-000000000000"],"#,
+            r#""m":{"k":"ffffffff-ffff-ffff-ffff-ffffffffffff"}}"#
+        );
+        assert_eq!(convert_round_trip(s, &msg, &converters).unwrap(), s);
+        assert!(convert_round_trip(r#"{"v":"123e4567"}"#, &msg, &converters).is_err());
+        assert!(convert_round_trip(r#"{"v":{}}"#, &msg, &converters).is_err());
+        assert!(convert_round_trip(s, &msg, &Converters::new()).is_err());
+    }
+
+    #[test]
+    fn test_converters_precedence() {
+        let mut converters = Converters::new();
+        converters.register("google.type.Date", Rc::new(Constant(r#""date""#)));
+        converters.register("google.protobuf.Duration", Rc::new(Constant("0")));
+        assert_eq!(
+            convert_round_trip(
+                r#"{"v":"2024-05-01"}"#,
+                &holder(message_type("google.type.Date", vec![])),
+                &converters
+            )
+            .unwrap(),
+            r#"{"v":"date"}"#
+        );
+        assert_eq!(
+            convert_round_trip(
+                r#"{"v":"1s"}"#,
+                &holder(message_type("google.protobuf.Duration", vec![])),
+                &converters
+            )
+            .unwrap(),
+            r#"{"v":0}"#
+        );
+    }
+}
@@ -0,0 +1,685 @@
+use crate::json::{parse_token_with, parse_value, Iter, Surrogates, Token, Value};
+use crate::proto::*;
+
+use super::append::Append;
+use super::wkt::*;
+use super::*;
+
+// https://github.com/googleapis/googleapis/tree/master/google/type
+
+struct Date;
+struct TimeOfDay;
+struct Decimal;
+struct Money;
+struct LatLng;
+
+pub fn find(name: &str) -> Option<&'static dyn Converter> {
+    match name {
+        "google.type.Date" => Some(&Date),
+        "google.type.TimeOfDay" => Some(&TimeOfDay),
+        "google.type.Decimal" => Some(&Decimal),
+        "google.type.Money" => Some(&Money),
+        "google.type.LatLng" => Some(&LatLng),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Raw<'a> {
+    None,
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Raw<'a> {
+    fn int(self) -> Result<i64> {
+        match self {
+            Raw::None => Ok(0),
+            Raw::Varint(v) => Ok(v as i64),
+            _ => Err(Error::InvalidWireType),
+        }
+    }
+
+    fn double(self) -> Result<f64> {
+        match self {
+            Raw::None => Ok(0.0),
+            Raw::Fixed64(v) => Ok(f64::from_bits(v)),
+            _ => Err(Error::InvalidWireType),
+        }
+    }
+
+    fn bytes(self) -> Result<&'a [u8]> {
+        match self {
+            Raw::None => Ok(&[]),
+            Raw::Bytes(s) => Ok(s),
+            _ => Err(Error::InvalidWireType),
+        }
+    }
+}
+
+// Keeps the last value of each of the fields 1..=N.
+fn read_raw<'a, const N: usize>(dec: &mut Decoder<'a>) -> Result<[Raw<'a>; N]> {
+    let mut fields = [Raw::None; N];
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        if tag == 0 || tag as usize > N {
+            skip_field(dec, wire)?;
+            continue;
+        }
+        fields[tag as usize - 1] = match wire {
+            WIRE_VARINT => Raw::Varint(dec.read_varint().map_err(Error::from)?),
+            WIRE_64BIT => Raw::Fixed64(dec.read_64bit().map_err(Error::from)?),
+            WIRE_LEN_DELIM => Raw::Bytes(dec.read_data().map_err(Error::from)?),
+            _ => return Err(Error::InvalidWireType),
+        };
+    }
+    Ok(fields)
+}
+
+fn emit_int(enc: &mut Encoder, tag: u32, v: i64) {
+    if v != 0 {
+        enc.emit_varint(tag, v as u64);
+    }
+}
+
+fn emit_double(enc: &mut Encoder, tag: u32, v: f64) {
+    if v != 0.0 {
+        enc.emit_u64(tag, v.to_bits());
+    }
+}
+
+fn emit_string(enc: &mut Encoder, tag: u32, s: &str) {
+    if !s.is_empty() {
+        enc.emit_len_delim(tag, s.as_bytes());
+    }
+}
+
+fn parse_object(
+    what: &str,
+    it: &mut Iter,
+    lead: Token,
+    lone: Surrogates,
+) -> Result<Vec<(String, Value)>> {
+    match lead {
+        Token::Object => match parse_token_with(it, lead, lone).map_err(|e| invalid(what, e))? {
+            Value::Object(members) => Ok(members),
+            _ => unreachable!(),
+        },
+        _ => Err(Error::TypeMismatch),
+    }
+}
+
+// Splits "[-]<units>[.<nanos>]" into units and nanos of the same sign.
+fn parse_units_nanos(s: &str) -> Option<(i64, i32)> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (int, frac) = match s.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (s, None),
+    };
+    if int.is_empty() || int.len() > 18 || !int.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let units = int.parse::<i64>().ok()?;
+    let nanos = match frac {
+        Some(frac) => parse_nanos(frac.as_bytes())? as i32,
+        None => 0,
+    };
+    if neg {
+        Some((-units, -nanos))
+    } else {
+        Some((units, nanos))
+    }
+}
+
+fn schema(s: &str) -> Option<Value> {
+    parse_value(&mut Iter::new(s.as_bytes())).ok()
+}
+
+fn is_decimal(s: &str) -> bool {
+    let b = s.as_bytes();
+    let mut i = match b.first() {
+        Some(b'+' | b'-') => 1,
+        _ => 0,
+    };
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while *i < b.len() && b[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        *i - start
+    };
+    let mut n = digits(&mut i);
+    if i < b.len() && b[i] == b'.' {
+        i += 1;
+        n += digits(&mut i);
+    }
+    if n == 0 {
+        return false;
+    }
+    if i < b.len() && matches!(b[i], b'e' | b'E') {
+        i += 1;
+        if i < b.len() && matches!(b[i], b'+' | b'-') {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    i == b.len()
+}
+
+impl Converter for Date {
+    fn to_json(&self, buf: &mut Vec<u8>, dec: &mut Decoder, _: &ProtoToJsonOptions) -> Result<()> {
+        let [year, month, day] = read_raw::<3>(dec)?;
+        let (year, month, day) = (year.int()?, month.int()?, day.int()?);
+        if !(0..=9999).contains(&year) || !(0..=12).contains(&month) || !(0..=31).contains(&day) {
+            return Err(invalid(
+                "date",
+                format!("{}-{}-{} is out of range", year, month, day),
+            ));
+        }
+        buf.push(b'"');
+        append_padded(buf, year as u64, 4);
+        buf.push(b'-');
+        append_padded(buf, month as u64, 2);
+        buf.push(b'-');
+        append_padded(buf, day as u64, 2);
+        buf.push(b'"');
+        Ok(())
+    }
+
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        _: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()> {
+        let s = unescape_token("date", lead, opts.lone_surrogates)?;
+        let b = s.as_bytes();
+        let parsed = if b.len() == 10 && b[4] == b'-' && b[7] == b'-' {
+            parse_digits(&b[0..4])
+                .zip(parse_digits(&b[5..7]))
+                .zip(parse_digits(&b[8..10]))
+        } else {
+            None
+        };
+        let ((year, month), day) = parsed
+            .filter(|&((y, m), d)| {
+                m <= 12 && d <= 31 && (y == 0 || m == 0 || d <= days_in_month(y as i64, m))
+            })
+            .ok_or_else(|| invalid("date", format!("'{}'", s)))?;
+        emit_int(enc, 1, year as i64);
+        emit_int(enc, 2, month as i64);
+        emit_int(enc, 3, day as i64);
+        Ok(())
+    }
+
+    fn json_schema(&self) -> Option<Value> {
+        schema(r#"{"type":"string","format":"date"}"#)
+    }
+}
+
+impl Converter for TimeOfDay {
+    fn to_json(&self, buf: &mut Vec<u8>, dec: &mut Decoder, _: &ProtoToJsonOptions) -> Result<()> {
+        let [hours, minutes, seconds, nanos] = read_raw::<4>(dec)?;
+        let (hours, minutes, seconds, nanos) =
+            (hours.int()?, minutes.int()?, seconds.int()?, nanos.int()?);
+        // 24:00:00 is allowed for scenarios like business closing time
+        if !(0..=24).contains(&hours)
+            || !(0..=59).contains(&minutes)
+            || !(0..=60).contains(&seconds)
+            || !(0..=999_999_999).contains(&nanos)
+        {
+            return Err(invalid(
+                "time of day",
+                format!(
+                    "{}:{}:{}.{} is out of range",
+                    hours, minutes, seconds, nanos
+                ),
+            ));
+        }
+        buf.push(b'"');
+        append_padded(buf, hours as u64, 2);
+        buf.push(b':');
+        append_padded(buf, minutes as u64, 2);
+        buf.push(b':');
+        append_padded(buf, seconds as u64, 2);
+        append_nanos(buf, nanos as u32);
+        buf.push(b'"');
+        Ok(())
+    }
+
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        _: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()> {
+        let s = unescape_token("time of day", lead, opts.lone_surrogates)?;
+        let b = s.as_bytes();
+        let parsed = if b.len() >= 8 && b[2] == b':' && b[5] == b':' {
+            let nanos = match &b[8..] {
+                [] => Some(0),
+                [b'.', frac @ ..] => parse_nanos(frac),
+                _ => None,
+            };
+            parse_digits(&b[0..2])
+                .zip(parse_digits(&b[3..5]))
+                .zip(parse_digits(&b[6..8]))
+                .zip(nanos)
+        } else {
+            None
+        };
+        let (((hours, minutes), seconds), nanos) = parsed
+            .filter(|&(((h, m), s), _)| h <= 24 && m <= 59 && s <= 60)
+            .ok_or_else(|| invalid("time of day", format!("'{}'", s)))?;
+        emit_int(enc, 1, hours as i64);
+        emit_int(enc, 2, minutes as i64);
+        emit_int(enc, 3, seconds as i64);
+        emit_int(enc, 4, nanos as i64);
+        Ok(())
+    }
+
+    fn json_schema(&self) -> Option<Value> {
+        schema(r#"{"type":"string","pattern":"^[0-9]{2}:[0-9]{2}:[0-9]{2}(\\.[0-9]{1,9})?$"}"#)
+    }
+}
+
+impl Converter for Decimal {
+    fn to_json(
+        &self,
+        buf: &mut Vec<u8>,
+        dec: &mut Decoder,
+        opts: &ProtoToJsonOptions,
+    ) -> Result<()> {
+        let [value] = read_raw::<1>(dec)?;
+        let value = value.bytes()?;
+        match std::str::from_utf8(value) {
+            Ok(s) if s.is_empty() || is_decimal(s) => {
+                append_string(buf, value, opts.escape);
+                Ok(())
+            }
+            _ => Err(invalid(
+                "decimal",
+                format!("'{}'", String::from_utf8_lossy(value)),
+            )),
+        }
+    }
+
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        _: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()> {
+        let s = unescape_token("decimal", lead, opts.lone_surrogates)?;
+        if !is_decimal(&s) {
+            return Err(invalid("decimal", format!("'{}'", s)));
+        }
+        emit_string(enc, 1, &s);
+        Ok(())
+    }
+
+    fn json_schema(&self) -> Option<Value> {
+        schema(
+            r#"{"type":"string","pattern":"^([+-]?([0-9]+(\\.[0-9]*)?|\\.[0-9]+)([eE][+-]?[0-9]+)?)?$"}"#,
+        )
+    }
+}
+
+impl Converter for Money {
+    fn to_json(
+        &self,
+        buf: &mut Vec<u8>,
+        dec: &mut Decoder,
+        opts: &ProtoToJsonOptions,
+    ) -> Result<()> {
+        let [currency_code, units, nanos] = read_raw::<3>(dec)?;
+        let (units, nanos) = (units.int()?, nanos.int()? as i32);
+        if nanos.unsigned_abs() > 999_999_999
+            || (units < 0 && nanos > 0)
+            || (units > 0 && nanos < 0)
+        {
+            return Err(invalid(
+                "money",
+                format!("units {} and nanos {} are inconsistent", units, nanos),
+            ));
+        }
+        buf.extend_from_slice(b"{\"currencyCode\":");
+        let currency_code = check_utf8(currency_code.bytes()?, "currency_code", opts.utf8)?;
+        append_string(buf, &currency_code, opts.escape);
+        buf.extend_from_slice(b",\"amount\":\"");
+        if units < 0 || nanos < 0 {
+            buf.push(b'-');
+        }
+        units.unsigned_abs().append_into(buf);
+        if nanos != 0 {
+            buf.push(b'.');
+            append_padded(buf, nanos.unsigned_abs() as u64, 9);
+            while buf.last() == Some(&b'0') {
+                buf.pop();
+            }
+        }
+        buf.extend_from_slice(b"\"}");
+        Ok(())
+    }
+
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        it: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()> {
+        let mut currency_code = String::new();
+        let mut amount = (0, 0);
+        for (k, v) in parse_object("money", it, lead, opts.lone_surrogates)? {
+            match (k.as_str(), v) {
+                ("currencyCode", Value::String(s)) => currency_code = s,
+                ("amount", Value::String(s) | Value::Number(s)) => {
+                    amount = parse_units_nanos(&s)
+                        .ok_or_else(|| invalid("money", format!("amount '{}'", s)))?
+                }
+                (k, _) => return Err(invalid("money", format!("unexpected field '{}'", k))),
+            }
+        }
+        emit_string(enc, 1, &currency_code);
+        emit_int(enc, 2, amount.0);
+        emit_int(enc, 3, amount.1 as i64);
+        Ok(())
+    }
+
+    fn json_schema(&self) -> Option<Value> {
+        schema(concat!(
+            r#"{"type":"object","properties":{"currencyCode":{"type":"string"},"#,
+            r#""amount":{"type":"string","pattern":"^-?[0-9]+(\\.[0-9]{1,9})?$"}},"#,
+            r#""required":["currencyCode","amount"]}"#
+        ))
+    }
+}
+
+impl Converter for LatLng {
+    fn to_json(&self, buf: &mut Vec<u8>, dec: &mut Decoder, _: &ProtoToJsonOptions) -> Result<()> {
+        let [latitude, longitude] = read_raw::<2>(dec)?;
+        let (latitude, longitude) = (latitude.double()?, longitude.double()?);
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(invalid(
+                "lat lng",
+                format!("({}, {}) is out of range", latitude, longitude),
+            ));
+        }
+        buf.extend_from_slice(b"{\"latitude\":");
+        latitude.append_into(buf);
+        buf.extend_from_slice(b",\"longitude\":");
+        longitude.append_into(buf);
+        buf.push(b'}');
+        Ok(())
+    }
+
+    fn to_proto(
+        &self,
+        enc: &mut Encoder,
+        it: &mut Iter,
+        lead: Token,
+        opts: &JsonToProtoOptions,
+    ) -> Result<()> {
+        let mut latitude = 0.0;
+        let mut longitude = 0.0;
+        for (k, v) in parse_object("lat lng", it, lead, opts.lone_surrogates)? {
+            let v = match v {
+                Value::Number(s) => s.parse::<f64>().ok(),
+                _ => None,
+            };
+            match (k.as_str(), v) {
+                ("latitude", Some(v)) if (-90.0..=90.0).contains(&v) => latitude = v,
+                ("longitude", Some(v)) if (-180.0..=180.0).contains(&v) => longitude = v,
+                (k, _) => return Err(invalid("lat lng", format!("field '{}'", k))),
+            }
+        }
+        emit_double(enc, 1, latitude);
+        emit_double(enc, 2, longitude);
+        Ok(())
+    }
+
+    fn json_schema(&self) -> Option<Value> {
+        schema(concat!(
+            r#"{"type":"object","properties":"#,
+            r#"{"latitude":{"type":"number","minimum":-90,"maximum":90},"#,
+            r#""longitude":{"type":"number","minimum":-180,"maximum":180}},"#,
+            r#""required":["latitude","longitude"]}"#
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::metadata::Message;
+    use crate::trans::tests::*;
+    use crate::trans::{JsonToProtoOptions, ProtoToJsonOptions};
+
+    use super::*;
+
+    fn check(name: &str, cases: &[(&str, &str)], invalid: &[&str]) {
+        let msg = holder(message_type(name, vec![]));
+        for &(input, output) in cases {
+            let s = format!(r#"{{"v":{}}}"#, input);
+            let data = json_to_proto(&s, &msg).unwrap();
+            assert_eq!(
+                proto_to_json(&data, &msg).unwrap(),
+                format!(r#"{{"v":{}}}"#, output)
+            );
+        }
+        for &input in invalid {
+            let s = format!(r#"{{"v":{}}}"#, input);
+            assert!(json_to_proto(&s, &msg).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_date() {
+        let msg = holder(message_type("google.type.Date", vec![]));
+        assert_eq!(
+            json_to_proto(r#"{"v":"2024-05-01"}"#, &msg).unwrap(),
+            vec![10, 7, 8, 232, 15, 16, 5, 24, 1]
+        );
+        check(
+            "google.type.Date",
+            &[
+                (r#""2024-05-01""#, r#""2024-05-01""#),
+                (r#""2024-02-29""#, r#""2024-02-29""#),
+                (r#""0000-12-25""#, r#""0000-12-25""#),
+                (r#""2024-00-00""#, r#""2024-00-00""#),
+            ],
+            &[
+                r#""2023-02-29""#,
+                r#""2024-13-01""#,
+                r#""2024-5-1""#,
+                r#""2024-05-01T00:00:00Z""#,
+                r#"{"year":2024}"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        check(
+            "google.type.TimeOfDay",
+            &[
+                (r#""13:45:00""#, r#""13:45:00""#),
+                (r#""00:00:00.5""#, r#""00:00:00.500""#),
+                (r#""24:00:00""#, r#""24:00:00""#),
+            ],
+            &[
+                r#""13:45""#,
+                r#""25:00:00""#,
+                r#""13:60:00""#,
+                r#""13:45:00.""#,
+                r#""13:45:00Z""#,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_decimal() {
+        check(
+            "google.type.Decimal",
+            &[
+                (r#""1.50""#, r#""1.50""#),
+                (r#""-.5e-3""#, r#""-.5e-3""#),
+                (r#""12.""#, r#""12.""#),
+            ],
+            &[r#""""#, r#""1.2.3""#, r#""1e""#, r#""abc""#, r#"1.5"#],
+        );
+    }
+
+    #[test]
+    fn test_money() {
+        check(
+            "google.type.Money",
+            &[
+                (
+                    r#"{"currencyCode":"USD","amount":"1.50"}"#,
+                    r#"{"currencyCode":"USD","amount":"1.5"}"#,
+                ),
+                (
+                    r#"{"amount":"-0.000000001","currencyCode":"EUR"}"#,
+                    r#"{"currencyCode":"EUR","amount":"-0.000000001"}"#,
+                ),
+                (
+                    r#"{"currencyCode":"JPY","amount":-3}"#,
+                    r#"{"currencyCode":"JPY","amount":"-3"}"#,
+                ),
+                (r#"{}"#, r#"{"currencyCode":"","amount":"0"}"#),
+            ],
+            &[
+                r#"{"currencyCode":"USD","amount":"1.5.0"}"#,
+                r#"{"currencyCode":"USD","amount":"0.0000000001"}"#,
+                r#"{"currencyCode":"USD","units":1}"#,
+                r#""USD 1.50""#,
+            ],
+        );
+        let msg = holder(message_type("google.type.Money", vec![]));
+        let trans = |s: &str, lone_surrogates| {
+            let opts = JsonToProtoOptions {
+                google_types: true,
+                lone_surrogates,
+                ..Default::default()
+            };
+            json_to_proto_with(s, &msg, &opts)
+        };
+        let s = r#"{"v":{"currencyCode":"\udc00","amount":"1"}}"#;
+        assert!(trans(s, Surrogates::Reject).is_err());
+        assert_eq!(
+            trans(s, Surrogates::Replace).unwrap(),
+            json_to_proto(r#"{"v":{"currencyCode":"\ufffd","amount":"1"}}"#, &msg).unwrap()
+        );
+        assert_eq!(
+            proto_to_json(&[10, 4, 10, 2, 85, 255], &msg)
+                .unwrap_err()
+                .to_string(),
+            "wrap: invalid utf-8 in field 'currency_code': invalid utf-8 sequence of 1 bytes from index 1"
+        );
+    }
+
+    #[test]
+    fn test_lat_lng() {
+        check(
+            "google.type.LatLng",
+            &[(
+                r#"{"latitude":-33.5,"longitude":151}"#,
+                r#"{"latitude":-33.5,"longitude":151.0}"#,
+            )],
+            &[
+                r#"{"latitude":91,"longitude":0}"#,
+                r#"{"latitude":"1","longitude":0}"#,
+                r#"{"lat":1}"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        for name in [
+            "google.type.Date",
+            "google.type.TimeOfDay",
+            "google.type.Decimal",
+            "google.type.Money",
+            "google.type.LatLng",
+        ] {
+            assert!(find(name).unwrap().json_schema().is_some(), "{}", name);
+        }
+        let mut buf = Vec::new();
+        Money.json_schema().unwrap().write_into(&mut buf);
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            concat!(
+                r#"{"type":"object","properties":{"currencyCode":{"type":"string"},"#,
+                r#""amount":{"type":"string","pattern":"^-?[0-9]+(\\.[0-9]{1,9})?$"}},"#,
+                r#""required":["currencyCode","amount"]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_google_types_opt_in() {
+        let msg = holder(message_type("google.type.Date", vec![]));
+        let data = [10, 7, 8, 232, 15, 16, 5, 24, 1, 18, 0];
+        assert_eq!(
+            proto_to_json(&data, &msg).unwrap(),
+            r#"{"v":"2024-05-01","r":["0000-00-00"]}"#
+        );
+        assert_eq!(
+            proto_to_json_with(&data, &msg, &ProtoToJsonOptions::default()).unwrap(),
+            r#"{"v":{},"r":[{}]}"#
+        );
+    }
+
+    struct DateResolver;
+
+    impl TypeResolver for DateResolver {
+        fn resolve(&self, type_url: &str) -> Option<Rc<Message>> {
+            (type_url == "type.googleapis.com/google.type.Date")
+                .then(|| Rc::new(Message::new("google.type.Date".to_string(), vec![], false)))
+        }
+    }
+
+    #[test]
+    fn test_any() {
+        let msg = holder(message_type(ANY, vec![]));
+        let mut any = Encoder::new();
+        any.emit_len_delim(1, b"type.googleapis.com/google.type.Date");
+        any.emit_len_delim(2, &[8, 232, 15, 16, 5, 24, 1]);
+        let mut data = Encoder::new();
+        data.emit_len_delim(1, any.as_bytes());
+
+        let opts = ProtoToJsonOptions {
+            google_types: true,
+            resolver: Some(Rc::new(DateResolver)),
+            ..Default::default()
+        };
+        let s = proto_to_json_with(data.as_bytes(), &msg, &opts).unwrap();
+        assert_eq!(
+            s,
+            r#"{"v":{"@type":"type.googleapis.com\/google.type.Date","value":"2024-05-01"}}"#
+        );
+
+        let opts = JsonToProtoOptions {
+            google_types: true,
+            resolver: Some(Rc::new(DateResolver)),
+            ..Default::default()
+        };
+        assert_eq!(
+            json_to_proto_with(&s, &msg, &opts).unwrap(),
+            data.as_bytes()
+        );
+    }
+}
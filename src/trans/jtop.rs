@@ -1,38 +1,42 @@
-use base64::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
 
 use crate::json::*;
 use crate::metadata::*;
 use crate::proto::*;
 
+use super::number::ParseNumber;
 use super::*;
 
 macro_rules! write_num_field {
-    ($e: expr, $tag: expr, $s: expr, $ty: ty, $z: expr) => {
-        $s.parse::<$ty>()
-            .map(|v| {
-                if v != 0 as $ty {
-                    if $z {
-                        $e.emit_zigzag($tag, v as i64);
-                    } else {
-                        $e.emit_varint($tag, v as u64);
-                    }
+    ($e: expr, $tag: expr, $s: expr, $ty: ty, $keep: expr, $z: expr) => {
+        <$ty>::parse_number($s).map(|v| {
+            if $keep || v != 0 as $ty {
+                if $z {
+                    $e.emit_zigzag($tag, v as i64);
+                } else {
+                    $e.emit_varint($tag, v as u64);
                 }
-            })
-            .map_err(|e| Error::Wrap(e.into()))
+            }
+        })
     };
-    ($e: expr, $tag: expr, $s: expr, $ty: ty) => {
-        $s.parse::<$ty>()
-            .map(|v| {
-                if v != 0 as $ty {
-                    $e.write_varint(proto_key($tag, if ::std::mem::size_of::<$ty>() == 64 {
-                        WIRE_64BIT
-                    } else {
-                        WIRE_32BIT
-                    }));
-                    $e.write_slice(&v.to_le_bytes()[..]);
-                }
-            })
-            .map_err(|e| Error::Wrap(e.into()))
+    ($e: expr, $tag: expr, $s: expr, $ty: ty, $keep: expr) => {
+        <$ty>::parse_number($s).map(|v| {
+            if $keep || v != 0 as $ty {
+                $e.write_varint(proto_key($tag, if ::std::mem::size_of::<$ty>() == 8 {
+                    WIRE_64BIT
+                } else {
+                    WIRE_32BIT
+                }));
+                $e.write_slice(&v.to_le_bytes()[..]);
+            }
+        })
     };
 }
 
@@ -51,9 +55,7 @@ macro_rules! write_elem_fn {
             Token::Number(s) => ::std::str::from_utf8(s)
                 .map_err(|e| Error::Wrap(e.into()))
                 .and_then(|s| {
-                    s.parse::<$ty>()
-                        .map(|v| $enc.write_slice(&v.to_le_bytes()[..]))
-                        .map_err(|e| Error::Wrap(e.into()))
+                    <$ty>::parse_number(s).map(|v| $enc.write_slice(&v.to_le_bytes()[..]))
                 }),
             _ => Err(Error::UnexpectedToken),
         }
@@ -63,15 +65,13 @@ macro_rules! write_elem_fn {
             Token::Number(s) => ::std::str::from_utf8(s)
                 .map_err(|e| Error::Wrap(e.into()))
                 .and_then(|s| {
-                    s.parse::<$ty>()
-                        .map(|v| {
-                            if $z {
-                                $enc.write_zigzag(v as i64);
-                            } else {
-                                $enc.write_varint(v as u64);
-                            }
-                        })
-                        .map_err(|e| Error::Wrap(e.into()))
+                    <$ty>::parse_number(s).map(|v| {
+                        if $z {
+                            $enc.write_zigzag(v as i64);
+                        } else {
+                            $enc.write_varint(v as u64);
+                        }
+                    })
                 }),
             _ => Err(Error::UnexpectedToken),
         }
@@ -106,68 +106,111 @@ fn skip_value(it: &mut Iter, tok: Token) -> Result<()> {
 }
 
 #[allow(clippy::float_cmp)]
-fn trans_numeric(enc: &mut Encoder, kind: &Kind, tag: u32, s: &[u8]) -> Result<()> {
+fn trans_numeric(enc: &mut Encoder, field: &Field, tag: u32, s: &[u8]) -> Result<()> {
+    let keep = field.required;
     ::std::str::from_utf8(s)
         .map_err(|e| Error::Wrap(e.into()))
-        .and_then(|s| match kind {
-            Kind::Double => write_num_field!(enc, tag, s, f64),
-            Kind::Float => write_num_field!(enc, tag, s, f32),
-            Kind::Int32 => write_num_field!(enc, tag, s, i32, false),
-            Kind::Int64 => write_num_field!(enc, tag, s, i64, false),
-            Kind::Uint32 => write_num_field!(enc, tag, s, u32, false),
-            Kind::Uint64 => write_num_field!(enc, tag, s, u64, false),
-            Kind::Sint32 => write_num_field!(enc, tag, s, i32, true),
-            Kind::Sint64 => write_num_field!(enc, tag, s, i64, true),
-            Kind::Fixed32 => write_num_field!(enc, tag, s, u32),
-            Kind::Fixed64 => write_num_field!(enc, tag, s, u64),
-            Kind::Sfixed32 => write_num_field!(enc, tag, s, i32),
-            Kind::Sfixed64 => write_num_field!(enc, tag, s, i64),
+        .and_then(|s| match field.kind {
+            Kind::Double => write_num_field!(enc, tag, s, f64, keep),
+            Kind::Float => write_num_field!(enc, tag, s, f32, keep),
+            Kind::Int32 => write_num_field!(enc, tag, s, i32, keep, false),
+            Kind::Int64 => write_num_field!(enc, tag, s, i64, keep, false),
+            Kind::Uint32 => write_num_field!(enc, tag, s, u32, keep, false),
+            Kind::Uint64 => write_num_field!(enc, tag, s, u64, keep, false),
+            Kind::Sint32 => write_num_field!(enc, tag, s, i32, keep, true),
+            Kind::Sint64 => write_num_field!(enc, tag, s, i64, keep, true),
+            Kind::Fixed32 => write_num_field!(enc, tag, s, u32, keep),
+            Kind::Fixed64 => write_num_field!(enc, tag, s, u64, keep),
+            Kind::Sfixed32 => write_num_field!(enc, tag, s, i32, keep),
+            Kind::Sfixed64 => write_num_field!(enc, tag, s, i64, keep),
             _ => Err(Error::TypeMismatch),
         })
 }
 
-fn trans_string(enc: &mut Encoder, s: &[u8], tag: u32) -> Result<()> {
+fn trans_string(
+    enc: &mut Encoder,
+    s: &[u8],
+    tag: u32,
+    field: &Field,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
     let mut z = Vec::with_capacity(s.len() - 2);
-    unescape_string(&s[1..s.len() - 1], &mut z).map_err(|e| Error::Wrap(e.into()))?;
-    if !z.is_empty() {
+    unescape_string_with(&s[1..s.len() - 1], &mut z, opts.lone_surrogates)
+        .map_err(|e| Error::Wrap(e.into()))?;
+    let z = check_utf8(&z, &field.name, opts.utf8)?;
+    if field.required || !z.is_empty() {
         enc.emit_len_delim(tag, &z);
     }
     Ok(())
 }
 
-fn trans_bytes(enc: &mut Encoder, s: &[u8], tag: u32) -> Result<()> {
+const PADDING_INDIFFERENT: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const STANDARD: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, PADDING_INDIFFERENT);
+const URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, PADDING_INDIFFERENT);
+
+// Accepts standard or URL-safe base64, padded or not, and ignores the line
+// breaks of MIME encoded input.
+fn decode_bytes(s: &[u8], z: &mut Vec<u8>) -> Result<()> {
+    let t = if s.contains(&b'\\') {
+        let mut t = Vec::with_capacity(s.len());
+        unescape_string(s, &mut t).map_err(|e| Error::Wrap(e.into()))?;
+        t.retain(|c| !c.is_ascii_whitespace());
+        Cow::Owned(t)
+    } else {
+        Cow::Borrowed(s)
+    };
+    let engine = if t.iter().any(|&c| c == b'-' || c == b'_') {
+        &URL_SAFE
+    } else {
+        &STANDARD
+    };
+    engine.decode_vec(&*t, z).map_err(|e| Error::Wrap(e.into()))
+}
+
+fn trans_bytes(enc: &mut Encoder, s: &[u8], tag: u32, keep: bool) -> Result<()> {
     let mut z = Vec::with_capacity(s.len() * 4 / 3);
-    BASE64_STANDARD
-        .decode_slice(&s[1..s.len() - 1], &mut z)
-        .map_err(|e| Error::Wrap(e.into()))?;
-    if !z.is_empty() {
+    decode_bytes(&s[1..s.len() - 1], &mut z)?;
+    if keep || !z.is_empty() {
         enc.emit_len_delim(tag, &z);
     }
     Ok(())
 }
 
-fn trans_map(enc: &mut Encoder, it: &mut Iter, tag: u32, entry: &Message) -> Result<()> {
+fn trans_map(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    tag: u32,
+    entry: &Message,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
     assert_eq!(entry.get_fields().len(), 2);
     let key_field = &entry.get_fields()[0];
     let val_field = &entry.get_fields()[1];
     let mut sub_enc = Encoder::new();
-    let mut key: Option<Token> = None;
+    let mut seen = HashSet::new();
+    let mut key: Option<&[u8]> = None;
     while let Some(tok) = it.next() {
         match tok {
             Token::ObjectClose if key.is_none() => return Ok(()),
             Token::Comma | Token::Colon => continue,
             _ => {
                 if let Some(k) = key {
+                    let name = || String::from_utf8_lossy(&k[1..k.len() - 1]).into_owned();
                     sub_enc.clear();
-                    trans_field(&mut sub_enc, it, 1, k, key_field)?;
-                    trans_field(&mut sub_enc, it, 2, tok, val_field)?;
+                    trans_field(&mut sub_enc, it, 1, Token::String(k), key_field, opts)?;
+                    if opts.strict && !seen.insert(sub_enc.as_bytes().to_vec()) {
+                        return Err(Error::DuplicateKey(name()));
+                    }
+                    trans_field(&mut sub_enc, it, 2, tok, val_field, opts)
+                        .map_err(|e| prefix_path(e, &name()))?;
                     let data = sub_enc.as_bytes();
                     if !data.is_empty() {
                         enc.emit_len_delim(tag, data);
                     }
                     key = None;
-                } else if matches!(tok, Token::String(_)) {
-                    key = Some(tok);
+                } else if let Token::String(k) = tok {
+                    key = Some(k);
                 } else {
                     return Err(Error::UnexpectedToken);
                 }
@@ -191,18 +234,49 @@ where
     Err(Error::UnexpectedEof)
 }
 
-fn trans_repeated(enc: &mut Encoder, it: &mut Iter, tag: u32, elem: &Field) -> Result<()> {
+// Lets an element take the quoted form of a number.
+fn quoted<'a, F>(
+    opts: &'a JsonToProtoOptions,
+    mut f: F,
+) -> impl FnMut(&mut Iter, Token) -> Result<()> + 'a
+where
+    F: FnMut(&mut Iter, Token) -> Result<()> + 'a,
+{
+    move |it, tok| match tok {
+        Token::String(s) => {
+            let s = unescape_quoted(s, opts)?;
+            check_finite(s.as_bytes(), opts)?;
+            f(it, Token::Number(s.as_bytes()))
+        }
+        _ => f(it, tok),
+    }
+}
+
+fn check_finite(s: &[u8], opts: &JsonToProtoOptions) -> Result<()> {
+    if opts.reject_non_finite && matches!(s, b"NaN" | b"Infinity" | b"-Infinity") {
+        let s = String::from_utf8_lossy(s);
+        return Err(Error::Wrap(
+            format!("non-finite value '{}' is not allowed", s).into(),
+        ));
+    }
+    Ok(())
+}
+
+fn trans_repeated(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    tag: u32,
+    elem: &Field,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
     match elem.kind {
         Kind::Message(ref msg) => {
             let mut z = Encoder::new();
-            trans_repeated_impl(it, |it, tok| match tok {
-                Token::Object => {
-                    z.clear();
-                    trans_message(&mut z, it, msg)?;
-                    enc.emit_len_delim(tag, z.as_bytes());
-                    Ok(())
-                }
-                _ => Err(Error::UnexpectedToken),
+            trans_repeated_impl(it, |it, tok| {
+                z.clear();
+                trans_message_value(&mut z, it, tok, msg, opts)?;
+                enc.emit_len_delim(tag, z.as_bytes());
+                Ok(())
             })
         }
         Kind::String => {
@@ -210,9 +284,9 @@ fn trans_repeated(enc: &mut Encoder, it: &mut Iter, tag: u32, elem: &Field) -> R
             trans_repeated_impl(it, |_, tok| match tok {
                 Token::String(s) => {
                     z.clear();
-                    unescape_string(&s[1..s.len() - 1], &mut z)
+                    unescape_string_with(&s[1..s.len() - 1], &mut z, opts.lone_surrogates)
                         .map_err(|e| Error::Wrap(e.into()))?;
-                    enc.emit_len_delim(tag, &z);
+                    enc.emit_len_delim(tag, &check_utf8(&z, &elem.name, opts.utf8)?);
                     Ok(())
                 }
                 _ => Err(Error::UnexpectedToken),
@@ -223,9 +297,7 @@ fn trans_repeated(enc: &mut Encoder, it: &mut Iter, tag: u32, elem: &Field) -> R
             trans_repeated_impl(it, |_, tok| match tok {
                 Token::String(s) => {
                     z.clear();
-                    BASE64_STANDARD
-                        .decode_slice(&s[1..s.len() - 1], &mut z)
-                        .map_err(|e| Error::Wrap(e.into()))?;
+                    decode_bytes(&s[1..s.len() - 1], &mut z)?;
                     enc.emit_len_delim(tag, &z);
                     Ok(())
                 }
@@ -236,18 +308,34 @@ fn trans_repeated(enc: &mut Encoder, it: &mut Iter, tag: u32, elem: &Field) -> R
             let mut packed = Encoder::new();
             match elem.kind {
                 Kind::Bool => trans_repeated_impl(it, write_elem_fn!(packed, bool)),
-                Kind::Double => trans_repeated_impl(it, write_elem_fn!(packed, f64)),
-                Kind::Float => trans_repeated_impl(it, write_elem_fn!(packed, f32)),
-                Kind::Int32 => trans_repeated_impl(it, write_elem_fn!(packed, i32, false)),
-                Kind::Int64 => trans_repeated_impl(it, write_elem_fn!(packed, i64, false)),
-                Kind::Uint32 => trans_repeated_impl(it, write_elem_fn!(packed, u32, false)),
-                Kind::Uint64 => trans_repeated_impl(it, write_elem_fn!(packed, u64, false)),
-                Kind::Sint32 => trans_repeated_impl(it, write_elem_fn!(packed, i32, true)),
-                Kind::Sint64 => trans_repeated_impl(it, write_elem_fn!(packed, i64, true)),
-                Kind::Fixed32 => trans_repeated_impl(it, write_elem_fn!(packed, u32)),
-                Kind::Fixed64 => trans_repeated_impl(it, write_elem_fn!(packed, u64)),
-                Kind::Sfixed32 => trans_repeated_impl(it, write_elem_fn!(packed, i32)),
-                Kind::Sfixed64 => trans_repeated_impl(it, write_elem_fn!(packed, i64)),
+                Kind::Double => trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, f64))),
+                Kind::Float => trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, f32))),
+                Kind::Int32 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i32, false)))
+                }
+                Kind::Int64 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i64, false)))
+                }
+                Kind::Uint32 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, u32, false)))
+                }
+                Kind::Uint64 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, u64, false)))
+                }
+                Kind::Sint32 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i32, true)))
+                }
+                Kind::Sint64 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i64, true)))
+                }
+                Kind::Fixed32 => trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, u32))),
+                Kind::Fixed64 => trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, u64))),
+                Kind::Sfixed32 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i32)))
+                }
+                Kind::Sfixed64 => {
+                    trans_repeated_impl(it, quoted(opts, write_elem_fn!(packed, i64)))
+                }
                 _ => return Err(Error::TypeMismatch),
             }?;
             if !packed.is_empty() {
@@ -258,9 +346,135 @@ fn trans_repeated(enc: &mut Encoder, it: &mut Iter, tag: u32, elem: &Field) -> R
     }
 }
 
-fn trans_embedded_message(enc: &mut Encoder, it: &mut Iter, tag: u32, msg: &Message) -> Result<()> {
+fn find_any_type(mut it: Iter, lone: Surrogates) -> Result<Option<String>> {
+    let mut key: Option<&[u8]> = None;
+    while let Some(tok) = it.next() {
+        match tok {
+            Token::ObjectClose if key.is_none() => return Ok(None),
+            Token::Comma | Token::Colon => continue,
+            _ => {
+                if let Some(k) = key {
+                    if k == b"\"@type\"" {
+                        return wkt::unescape_token("@type", tok, lone).map(Some);
+                    }
+                    skip_value(&mut it, tok)?;
+                    key = None;
+                } else if let Token::String(k) = tok {
+                    key = Some(k);
+                } else {
+                    return Err(Error::UnexpectedToken);
+                }
+            }
+        }
+    }
+    Err(Error::UnexpectedEof)
+}
+
+// "@type" may appear anywhere in the object, so it is looked up ahead before the fields are
+// translated.
+fn trans_any(enc: &mut Encoder, it: &mut Iter, opts: &JsonToProtoOptions) -> Result<()> {
+    let type_url = match find_any_type(it.clone(), opts.lone_surrogates)? {
+        Some(type_url) => type_url,
+        None => {
+            return match it.next() {
+                Some(Token::ObjectClose) => Ok(()),
+                Some(_) => Err(Error::Wrap("missing @type in Any".into())),
+                None => Err(Error::UnexpectedEof),
+            }
+        }
+    };
+    let msg = wkt::resolve_any(opts.resolver.as_deref(), &type_url)?;
+    let mut value = Encoder::new();
+    // Mirrors the writer: WKTs with a JSON mapping and types with a converter sit under "value".
+    if wkt::has_json_mapping(msg.get_name())
+        || opts
+            .converters
+            .lookup(msg.get_name(), opts.google_types)
+            .is_some()
+    {
+        let mut key: Option<&[u8]> = None;
+        loop {
+            let tok = it.next().ok_or(Error::UnexpectedEof)?;
+            match tok {
+                Token::ObjectClose if key.is_none() => break,
+                Token::Comma | Token::Colon => continue,
+                _ => match key {
+                    Some(b"\"@type\"") => {
+                        skip_value(it, tok)?;
+                        key = None;
+                    }
+                    Some(b"\"value\"") => {
+                        trans_message_value(&mut value, it, tok, &msg, opts)?;
+                        key = None;
+                    }
+                    Some(k) => {
+                        return Err(Error::Wrap(
+                            format!("unexpected field {} in Any", String::from_utf8_lossy(k))
+                                .into(),
+                        ))
+                    }
+                    None => match tok {
+                        Token::String(k) => key = Some(k),
+                        _ => return Err(Error::UnexpectedToken),
+                    },
+                },
+            }
+        }
+    } else {
+        trans_message(&mut value, it, &msg, true, opts)?;
+    }
+    enc.emit_len_delim(1, type_url.as_bytes());
+    if !value.is_empty() {
+        enc.emit_len_delim(2, value.as_bytes());
+    }
+    Ok(())
+}
+
+fn trans_message_value(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    lead: Token,
+    msg: &Message,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    if let Some(conv) = opts.converters.lookup(msg.get_name(), opts.google_types) {
+        return conv.to_proto(enc, it, lead, opts);
+    }
+    if let Some(field) = wkt::wrapper_field(msg.get_name()) {
+        return trans_field(enc, it, field.tag, lead, &field, opts);
+    }
+    match (msg.get_name(), lead) {
+        (wkt::TIMESTAMP, lead) => wkt::timestamp_from_json(enc, lead, opts),
+        (wkt::DURATION, lead) => wkt::duration_from_json(enc, lead, opts),
+        (wkt::ANY, Token::Object) => trans_any(enc, it, opts),
+        (wkt::STRUCT, lead) => wkt::struct_from_json(enc, it, lead, opts),
+        (wkt::VALUE, lead) => wkt::value_from_json(enc, it, lead, opts),
+        (wkt::LIST_VALUE, lead) => wkt::list_value_from_json(enc, it, lead, opts),
+        (wkt::FIELD_MASK, lead) => wkt::field_mask_from_json(enc, lead, opts),
+        (_, Token::Object) => trans_message(enc, it, msg, false, opts),
+        (
+            _,
+            Token::Null
+            | Token::True
+            | Token::False
+            | Token::Number(_)
+            | Token::String(_)
+            | Token::Array,
+        ) => Err(Error::TypeMismatch),
+        _ => Err(Error::UnexpectedToken),
+    }
+}
+
+fn trans_embedded_message(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    tag: u32,
+    lead: Token,
+    msg: &Message,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
     let mut embedded = Encoder::new();
-    trans_message(&mut embedded, it, msg)?;
+    trans_message_value(&mut embedded, it, lead, msg, opts)?;
     enc.emit_len_delim(tag, embedded.as_bytes());
     Ok(())
 }
@@ -271,18 +485,30 @@ fn trans_field(
     tag: u32,
     lead: Token,
     field: &Field,
+    opts: &JsonToProtoOptions,
 ) -> Result<()> {
+    if let Kind::Message(ref msg) = field.kind {
+        // null leaves a message field unset, except for Value where it is a kind of its own
+        if !field.repeated && (!matches!(lead, Token::Null) || msg.get_name() == wkt::VALUE) {
+            return trans_embedded_message(enc, it, tag, lead, msg, opts);
+        }
+    }
     match lead {
         Token::String(s) => match field.kind {
-            Kind::String => trans_string(enc, s, tag),
-            Kind::Bytes => trans_bytes(enc, s, tag),
-            _ => Err(Error::TypeMismatch),
+            Kind::String => trans_string(enc, s, tag, field, opts),
+            Kind::Bytes => trans_bytes(enc, s, tag, field.required),
+            Kind::Bool | Kind::Map(_) | Kind::Message(_) => Err(Error::TypeMismatch),
+            _ => {
+                let s = unescape_quoted(s, opts)?;
+                check_finite(s.as_bytes(), opts)?;
+                trans_numeric(enc, field, tag, s.as_bytes())
+            }
         },
-        Token::Number(n) => trans_numeric(enc, &field.kind, tag, n),
+        Token::Number(n) => trans_numeric(enc, field, tag, n),
         Token::True | Token::False => match field.kind {
             Kind::Bool => {
-                if matches!(lead, Token::True) {
-                    enc.emit_varint(tag, 1);
+                if field.required || matches!(lead, Token::True) {
+                    enc.emit_varint(tag, matches!(lead, Token::True) as u64);
                 }
                 Ok(())
             }
@@ -297,13 +523,12 @@ fn trans_field(
             }
         }
         Token::Object => match field.kind {
-            Kind::Message(ref msg) => trans_embedded_message(enc, it, tag, msg),
-            Kind::Map(ref entry) => trans_map(enc, it, tag, entry),
+            Kind::Map(ref entry) => trans_map(enc, it, tag, entry, opts),
             _ => Err(Error::TypeMismatch),
         },
         Token::Array => {
             if field.repeated {
-                trans_repeated(enc, it, tag, field)
+                trans_repeated(enc, it, tag, field, opts)
             } else {
                 Err(Error::TypeMismatch)
             }
@@ -312,7 +537,38 @@ fn trans_field(
     }
 }
 
-fn trans_message(enc: &mut Encoder, it: &mut Iter, msg: &Message) -> Result<()> {
+// Nested unknown or duplicate keys report their path relative to the enclosing key.
+fn prefix_path(e: Error, name: &str) -> Error {
+    match e {
+        Error::UnknownField(p) => Error::UnknownField(format!("{}.{}", name, p)),
+        Error::DuplicateKey(p) => Error::DuplicateKey(format!("{}.{}", name, p)),
+        e => e,
+    }
+}
+
+fn unescape_quoted<'a>(k: &'a [u8], opts: &JsonToProtoOptions) -> Result<Cow<'a, str>> {
+    let k = &k[1..k.len() - 1];
+    if k.contains(&b'\\') {
+        let mut z = Vec::with_capacity(k.len());
+        unescape_string_with(k, &mut z, opts.lone_surrogates).map_err(|e| Error::Wrap(e.into()))?;
+        String::from_utf8(z)
+            .map(Cow::Owned)
+            .map_err(|e| Error::Wrap(e.into()))
+    } else {
+        ::std::str::from_utf8(k)
+            .map(Cow::Borrowed)
+            .map_err(|e| Error::Wrap(e.into()))
+    }
+}
+
+fn trans_message(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    msg: &Message,
+    in_any: bool,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    let mut seen = Vec::new();
     let mut key: Option<&[u8]> = None;
     while let Some(tok) = it.next() {
         match tok {
@@ -320,11 +576,26 @@ fn trans_message(enc: &mut Encoder, it: &mut Iter, msg: &Message) -> Result<()>
             Token::Comma | Token::Colon => continue,
             _ => {
                 if let Some(k) = key {
-                    let name = ::std::str::from_utf8(&k[1..k.len() - 1])
-                        .map_err(|e| Error::Wrap(e.into()))?;
+                    let name = unescape_quoted(k, opts)?;
+                    let name = name.as_ref();
                     if let Some(field) = msg.get_by_name(name) {
-                        trans_field(enc, it, field.tag, tok, &field)?;
+                        if opts.strict {
+                            if seen.contains(&field.tag) {
+                                return Err(Error::DuplicateKey(name.to_string()));
+                            }
+                            seen.push(field.tag);
+                        }
+                        trans_field(enc, it, field.tag, tok, field, opts)
+                            .map_err(|e| prefix_path(e, name))?;
                     } else {
+                        if !(in_any && name == "@type") {
+                            if opts.strict {
+                                return Err(Error::UnknownField(name.to_string()));
+                            }
+                            if let Some(ref f) = opts.on_unknown_field {
+                                f(msg.get_name(), name);
+                            }
+                        }
                         skip_value(it, tok)?;
                     }
                     key = None;
@@ -339,12 +610,43 @@ fn trans_message(enc: &mut Encoder, it: &mut Iter, msg: &Message) -> Result<()>
     Err(Error::UnexpectedEof)
 }
 
-pub fn trans_json_to_proto(enc: &mut Encoder, it: &mut Iter, msg: &Message) -> Result<()> {
+// Called with the message name and key of each unknown field when not strict.
+pub type UnknownFieldHandler = Rc<dyn Fn(&str, &str)>;
+
+#[derive(Clone, Default)]
+pub struct JsonToProtoOptions {
+    pub allow_partial: bool,
+    pub resolver: Option<Rc<dyn TypeResolver>>,
+    pub google_types: bool,
+    pub converters: Converters,
+    pub reject_non_finite: bool,
+    // Rejects unknown fields and keys that appear twice in one object.
+    pub strict: bool,
+    pub on_unknown_field: Option<UnknownFieldHandler>,
+    pub utf8: Utf8Policy,
+    pub lone_surrogates: Surrogates,
+}
+
+pub fn trans_json_to_proto_with(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    msg: &Message,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    let start = enc.as_bytes().len();
     match it.next() {
-        Some(Token::Object) => trans_message(enc, it, msg),
-        None => Err(Error::UnexpectedEof),
-        _ => Err(Error::UnexpectedToken),
+        Some(Token::Object) => trans_message(enc, it, msg, false, opts)?,
+        None => return Err(Error::UnexpectedEof),
+        _ => return Err(Error::UnexpectedToken),
+    }
+    if !opts.allow_partial {
+        check_initialized(&enc.as_bytes()[start..], msg)?;
     }
+    Ok(())
+}
+
+pub fn trans_json_to_proto(enc: &mut Encoder, it: &mut Iter, msg: &Message) -> Result<()> {
+    trans_json_to_proto_with(enc, it, msg, &JsonToProtoOptions::default())
 }
 
 #[cfg(test)]
@@ -370,6 +672,31 @@ mod tests {
         test_trans_json_to_proto(s);
     }
 
+    #[test]
+    fn test_trans_json_to_proto_fixed_and_bytes() {
+        let msg = Message::new(
+            "pbmsg.Fixed".to_string(),
+            vec![
+                field("d", 1, Kind::Double, false),
+                field("x", 2, Kind::Fixed64, false),
+                field("f", 3, Kind::Float, false),
+                field("b", 4, Kind::Bytes, false),
+            ],
+            true,
+        );
+        let mut enc = Encoder::new();
+        let s = r#"{"d":1.5,"x":7,"f":0.5,"b":"aGk="}"#;
+        trans_json_to_proto(&mut enc, &mut Iter::new(s.as_bytes()), &msg).unwrap();
+        let mut want = vec![9];
+        want.extend_from_slice(&1.5f64.to_le_bytes());
+        want.push(17);
+        want.extend_from_slice(&7u64.to_le_bytes());
+        want.push(29);
+        want.extend_from_slice(&0.5f32.to_le_bytes());
+        want.extend_from_slice(&[34, 2, b'h', b'i']);
+        assert_eq!(enc.as_bytes(), &want[..]);
+    }
+
     #[test]
     fn test_trans_json_to_proto_case1() {
         let s = r#"{"a":"a","b":true,"c":1,"d":{"a":2,"b":"b"},"e":[3,4,5],"f":["f0","f1","f2"],"g":[{"a":6,"s":"s0"},{"a":7,"s":"s1"}]}"#;
@@ -381,4 +708,236 @@ mod tests {
         let s = r#"{"a":"","b":false,"c":0,"d":{"a":0,"b":""},"e":[0,0,0],"f":["","",""],"g":[{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""},{"a":0,"s":""}]}"#;
         test_trans_json_to_proto(s);
     }
+
+    #[test]
+    fn test_trans_json_to_proto_numbers() {
+        let msg = get_msg_foo_type();
+        let trans = |s: &str| {
+            let mut enc = Encoder::new();
+            trans_json_to_proto(&mut enc, &mut Iter::new(s.as_bytes()), &msg)
+                .map(|_| enc.into_inner())
+        };
+        for s in [
+            r#"{"c":5,"e":[1,2,300]}"#,
+            r#"{"c":"5","e":["1","2e0","300"]}"#,
+            r#"{"c":5.0,"e":[1e0,0.2e1,3E+2]}"#,
+            r#"{"c":"0.5e1","e":[1,"20e-1",300.000]}"#,
+            r#"{"c":"\u0035","e":["\u0031","2",300]}"#,
+        ] {
+            assert_eq!(trans(s).unwrap(), vec![24, 5, 42, 4, 1, 2, 172, 2], "{}", s);
+        }
+        for (s, e) in [
+            (r#"{"c":1.5}"#, "invalid int32 value '1.5': not an integer"),
+            (
+                r#"{"c":"1e-1"}"#,
+                "invalid int32 value '1e-1': not an integer",
+            ),
+            (r#"{"c":3e9}"#, "invalid int32 value '3e9': out of range"),
+            (r#"{"e":[1,"x"]}"#, "invalid int32 value 'x': not a number"),
+            (r#"{"c":" 1"}"#, "invalid int32 value ' 1': not a number"),
+        ] {
+            assert_eq!(trans(s).unwrap_err().to_string(), format!("wrap: {}", e));
+        }
+        assert!(matches!(trans(r#"{"b":"true"}"#), Err(Error::TypeMismatch)));
+    }
+
+    #[test]
+    fn test_trans_json_to_proto_non_finite() {
+        let msg = get_msg_floats_type();
+        let trans = |s: &str, opts: &JsonToProtoOptions| {
+            let mut enc = Encoder::new();
+            trans_json_to_proto_with(&mut enc, &mut Iter::new(s.as_bytes()), &msg, opts)
+                .map(|_| enc.into_inner())
+        };
+        let input = r#"{"d":"NaN","f":["Infinity",1.5,"-Infinity"],"m":{"a":"-Infinity"}}"#;
+        assert_eq!(
+            trans(input, &JsonToProtoOptions::default()).unwrap(),
+            get_floats_non_finite()
+        );
+
+        let opts = JsonToProtoOptions {
+            reject_non_finite: true,
+            ..Default::default()
+        };
+        for input in [
+            r#"{"d":"NaN"}"#,
+            r#"{"f":["Infinity"]}"#,
+            r#"{"m":{"a":"-Infinity"}}"#,
+        ] {
+            assert!(trans(input, &opts)
+                .unwrap_err()
+                .to_string()
+                .starts_with("wrap: non-finite value '"));
+        }
+    }
+
+    #[test]
+    fn test_trans_json_to_proto_bytes() {
+        let msg = get_msg_blobs_type();
+        let trans = |s: &str| {
+            let mut enc = Encoder::new();
+            trans_json_to_proto(&mut enc, &mut Iter::new(s.as_bytes()), &msg)
+                .map(|_| enc.into_inner())
+        };
+        for s in [
+            r#"{"b":"+/8=","r":["-_8=","+/8"],"m":{"a":"-_8"}}"#,
+            r#"{"b":"-_8","r":["+/8=","-_8"],"m":{"a":"+/8="}}"#,
+            r#"{"b":"+/\r\n8=","r":["-_\n8","+/8"],"m":{"a":"+\/8"}}"#,
+        ] {
+            assert_eq!(trans(s).unwrap(), get_blobs(), "{}", s);
+        }
+        assert!(trans(r#"{"b":"+_8="}"#).is_err());
+        assert!(trans(r#"{"b":"+/8 ="}"#).is_err());
+    }
+
+    #[test]
+    fn test_trans_json_to_proto_strict() {
+        let entry = Message::new(
+            "pbmsg.Strict.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, get_msg_elem_type(), false),
+            ],
+            false,
+        );
+        let msg = Message::new(
+            "pbmsg.Strict".to_string(),
+            get_msg_foo_type()
+                .get_fields()
+                .iter()
+                .cloned()
+                .chain([field("m", 8, Kind::Map(Rc::new(entry)), false)])
+                .collect(),
+            false,
+        );
+        let trans = |s: &str, opts: &JsonToProtoOptions| {
+            let mut enc = Encoder::new();
+            trans_json_to_proto_with(&mut enc, &mut Iter::new(s.as_bytes()), &msg, opts)
+                .map(|_| enc.into_inner())
+        };
+        let strict = JsonToProtoOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let s = r#"{"a":"a","x":1,"d":{"a":2,"z":[]},"m":{"k":{"a":1}}}"#;
+        assert_eq!(
+            trans(s, &Default::default()).unwrap(),
+            trans(r#"{"a":"a","d":{"a":2},"m":{"k":{"a":1}}}"#, &strict).unwrap()
+        );
+        for (s, e) in [
+            (r#"{"x":1}"#, "unknown field: x"),
+            (r#"{"d":{"a":2,"z":[]}}"#, "unknown field: d.z"),
+            (r#"{"g":[{"a":1},{"q":2}]}"#, "unknown field: g.q"),
+            (r#"{"m":{"k":{"z":1}}}"#, "unknown field: m.k.z"),
+            (r#"{"a":"a","c":1,"a":"b"}"#, "duplicate key: a"),
+            (r#"{"d":{"b":"x","b":"y"}}"#, "duplicate key: d.b"),
+            (r#"{"m":{"k":{},"j":{},"k":{}}}"#, "duplicate key: m.k"),
+        ] {
+            assert_eq!(trans(s, &strict).unwrap_err().to_string(), e);
+        }
+
+        let unknown = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = unknown.clone();
+        let lenient = JsonToProtoOptions {
+            on_unknown_field: Some(Rc::new(move |msg: &str, key: &str| {
+                log.borrow_mut().push(format!("{}.{}", msg, key))
+            })),
+            ..Default::default()
+        };
+        trans(s, &lenient).unwrap();
+        assert_eq!(
+            *unknown.borrow(),
+            vec!["pbmsg.Strict.x", "pbmsg.Foo.Embed.z"]
+        );
+    }
+
+    #[test]
+    fn test_trans_json_to_proto_utf8() {
+        let msg = get_msg_foo_type();
+        let trans = |s: &[u8], utf8| {
+            let opts = JsonToProtoOptions {
+                utf8,
+                ..Default::default()
+            };
+            let mut enc = Encoder::new();
+            trans_json_to_proto_with(&mut enc, &mut Iter::new(s), &msg, &opts)
+                .map(|_| enc.into_inner())
+        };
+        let input = b"{\"a\":\"\xffa\",\"f\":[\"b\xc3\"]}";
+        assert_eq!(
+            trans(input, Utf8Policy::Reject).unwrap_err().to_string(),
+            "wrap: invalid utf-8 in field 'a': invalid utf-8 sequence of 1 bytes from index 0"
+        );
+        assert_eq!(
+            trans(b"{\"f\":[\"b\xc3\"]}", Utf8Policy::Reject)
+                .unwrap_err()
+                .to_string(),
+            "wrap: invalid utf-8 in field 'f': incomplete utf-8 byte sequence from index 1"
+        );
+        assert_eq!(
+            trans(input, Utf8Policy::Replace).unwrap(),
+            b"\x0a\x04\xef\xbf\xbda\x32\x04b\xef\xbf\xbd"
+        );
+        assert_eq!(
+            trans(input, Utf8Policy::Pass).unwrap(),
+            b"\x0a\x02\xffa\x32\x02b\xc3"
+        );
+    }
+
+    #[test]
+    fn test_trans_json_to_proto_surrogates() {
+        let entry = Message::new(
+            "pbmsg.Text.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Int32, false),
+            ],
+            false,
+        );
+        let msg = Message::new(
+            "pbmsg.Text".to_string(),
+            vec![
+                field("s", 1, Kind::String, false),
+                field("r", 2, Kind::String, true),
+                field("m", 3, Kind::Map(Rc::new(entry)), false),
+            ],
+            false,
+        );
+        let trans = |s: &str, lone_surrogates| {
+            let opts = JsonToProtoOptions {
+                lone_surrogates,
+                ..Default::default()
+            };
+            let mut enc = Encoder::new();
+            trans_json_to_proto_with(&mut enc, &mut Iter::new(s.as_bytes()), &msg, &opts)
+                .map(|_| enc.into_inner())
+        };
+        assert_eq!(
+            trans(
+                r#"{"\u0073":"\ud83d\ude00","r":["\uD83D\uDE00"],"m":{"\ud83d\ude00":1}}"#,
+                Surrogates::Reject
+            )
+            .unwrap(),
+            trans(r#"{"s":"😀","r":["😀"],"m":{"😀":1}}"#, Surrogates::Reject).unwrap()
+        );
+        for s in [
+            r#"{"s":"\ud83d"}"#,
+            r#"{"r":["a","\ude00"]}"#,
+            r#"{"m":{"\ud83dx":1}}"#,
+            r#"{"\ud83d":1}"#,
+        ] {
+            assert!(trans(s, Surrogates::Reject)
+                .unwrap_err()
+                .to_string()
+                .starts_with("wrap: lone surrogate: '\\ud"));
+        }
+        assert_eq!(
+            trans(
+                r#"{"s":"\ud83d","r":["\ude00"],"m":{"\ud83dx":1}}"#,
+                Surrogates::Replace
+            )
+            .unwrap(),
+            trans(r#"{"s":"�","r":["�"],"m":{"�x":1}}"#, Surrogates::Reject).unwrap()
+        );
+    }
 }
@@ -0,0 +1,171 @@
+use crate::json::is_number;
+
+use super::*;
+
+// https://protobuf.dev/programming-guides/proto3/#json
+//
+// Integer fields also accept exact values written with a fraction or an exponent, such as
+// `1.0` or `1e3`.
+
+pub trait ParseNumber: Sized {
+    fn parse_number(s: &str) -> Result<Self>;
+}
+
+fn invalid(s: &str, ty: &str, reason: &str) -> Error {
+    Error::Wrap(format!("invalid {} value '{}': {}", ty, s, reason).into())
+}
+
+fn parse_integer(s: &str) -> result::Result<i128, &'static str> {
+    if !is_number(s.as_bytes()) {
+        return Err("not a number");
+    }
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (mantissa, exp) = match s.find(['e', 'E']) {
+        Some(i) => {
+            let exp = &s[i + 1..];
+            // a larger exponent can't produce an integer that fits
+            let exp = exp.parse::<i32>().unwrap_or(if exp.starts_with('-') {
+                i32::MIN / 2
+            } else {
+                i32::MAX / 2
+            });
+            (&s[..i], exp)
+        }
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int, frac);
+    let digits = digits.trim_start_matches('0');
+    let significant = digits.trim_end_matches('0');
+    if significant.is_empty() {
+        return Ok(0);
+    }
+    let exp = exp as i64 - frac.len() as i64 + (digits.len() - significant.len()) as i64;
+    if exp < 0 {
+        return Err("not an integer");
+    }
+    if significant.len() as i64 + exp > 39 {
+        return Err("out of range");
+    }
+    let v = significant
+        .parse::<i128>()
+        .ok()
+        .and_then(|v| v.checked_mul(10i128.checked_pow(exp as u32)?))
+        .ok_or("out of range")?;
+    Ok(if neg { -v } else { v })
+}
+
+macro_rules! impl_parse_integer {
+    ($ty: ty, $name: expr) => {
+        impl ParseNumber for $ty {
+            fn parse_number(s: &str) -> Result<Self> {
+                parse_integer(s)
+                    .and_then(|v| <$ty>::try_from(v).map_err(|_| "out of range"))
+                    .map_err(|e| invalid(s, $name, e))
+            }
+        }
+    };
+}
+
+impl_parse_integer!(i32, "int32");
+impl_parse_integer!(i64, "int64");
+impl_parse_integer!(u32, "uint32");
+impl_parse_integer!(u64, "uint64");
+
+impl ParseNumber for f64 {
+    fn parse_number(s: &str) -> Result<Self> {
+        match s {
+            "NaN" => return Ok(f64::NAN),
+            "Infinity" => return Ok(f64::INFINITY),
+            "-Infinity" => return Ok(f64::NEG_INFINITY),
+            _ => {}
+        }
+        if !is_number(s.as_bytes()) {
+            return Err(invalid(s, "double", "not a number"));
+        }
+        match s.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err(invalid(s, "double", "out of range")),
+        }
+    }
+}
+
+impl ParseNumber for f32 {
+    fn parse_number(s: &str) -> Result<Self> {
+        let v = f64::parse_number(s).map_err(|_| invalid(s, "float", "not a number"))?;
+        // values beyond the largest float that still round to it are accepted
+        match v as f32 {
+            f if f.is_finite() || !v.is_finite() => Ok(f),
+            _ => Err(invalid(s, "float", "out of range")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_integer() {
+        for (s, v) in [
+            ("0", 0),
+            ("-0", 0),
+            ("123", 123),
+            ("-123", -123),
+            ("1e3", 1000),
+            ("1E+3", 1000),
+            ("1.0", 1),
+            ("1.50e1", 15),
+            ("12300e-2", 123),
+            ("0.0e-5", 0),
+            ("0e99999999999", 0),
+            ("-2147483648", -2147483648),
+            ("2.147483647e9", 2147483647),
+        ] {
+            assert_eq!(i32::parse_number(s).unwrap(), v, "{}", s);
+        }
+        assert_eq!(u64::parse_number("18446744073709551615").unwrap(), u64::MAX);
+        assert_eq!(
+            i64::parse_number("-9.223372036854775808e18").unwrap(),
+            i64::MIN
+        );
+
+        for (s, reason) in [
+            ("1.5", "not an integer"),
+            ("1e-1", "not an integer"),
+            ("123e-5", "not an integer"),
+            ("2147483648", "out of range"),
+            ("1e10", "out of range"),
+            ("1e99999999999", "out of range"),
+            ("01", "not a number"),
+            ("1.", "not a number"),
+            ("+1", "not a number"),
+            ("", "not a number"),
+            (" 1", "not a number"),
+            ("0x10", "not a number"),
+        ] {
+            assert_eq!(
+                i32::parse_number(s).unwrap_err().to_string(),
+                format!("wrap: invalid int32 value '{}': {}", s, reason)
+            );
+        }
+        assert!(u32::parse_number("-1").is_err());
+        assert!(u64::parse_number("-1e0").is_err());
+    }
+
+    #[test]
+    fn test_parse_float() {
+        assert_eq!(f64::parse_number("-1.5e-3").unwrap(), -0.0015);
+        assert_eq!(f32::parse_number("3.4028235e38").unwrap(), f32::MAX);
+        assert!(f32::parse_number("3.5e38").is_err());
+        assert!(f64::parse_number("1e400").is_err());
+        assert!(f64::parse_number("NaN").unwrap().is_nan());
+        assert_eq!(f32::parse_number("-Infinity").unwrap(), f32::NEG_INFINITY);
+        assert!(f64::parse_number("inf").is_err());
+        assert!(f64::parse_number("-NaN").is_err());
+        assert!(f64::parse_number(".5").is_err());
+    }
+}
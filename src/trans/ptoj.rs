@@ -1,3 +1,6 @@
+use std::rc::Rc;
+
+use base64::engine::Config;
 use base64::prelude::*;
 
 use crate::json;
@@ -12,6 +15,47 @@ macro_rules! write_primitive {
         ($v).append_into($b);
         Ok(())
     }};
+    ($b: expr, $v: expr, $quote: expr) => {{
+        append_number($b, $v, $quote);
+        Ok(())
+    }};
+}
+
+fn append_finite<T: Append>(buf: &mut Vec<u8>, v: T, opts: &ProtoToJsonOptions) -> Result<()> {
+    if opts.reject_non_finite && !v.is_finite() {
+        return Err(Error::Wrap("non-finite value is not allowed".into()));
+    }
+    if opts.canonical && v.is_finite() {
+        let mut s = Vec::new();
+        v.append_into(&mut s);
+        json::write_canonical_number(&s, buf).map_err(|e| Error::Wrap(e.into()))
+    } else {
+        v.append_into(buf);
+        Ok(())
+    }
+}
+
+// Canonical output sorts the members of each object by key once it is complete.
+fn sort_members(
+    buf: &mut Vec<u8>,
+    starts: &mut Vec<usize>,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    if opts.canonical {
+        json::sort_members(buf, starts).map_err(|e| Error::Wrap(e.into()))?;
+    }
+    starts.clear();
+    Ok(())
+}
+
+fn append_number<T: Append>(buf: &mut Vec<u8>, v: T, quote: bool) {
+    if quote {
+        buf.push(b'"');
+        v.append_into(buf);
+        buf.push(b'"');
+    } else {
+        v.append_into(buf);
+    }
 }
 
 enum Value<'a> {
@@ -44,7 +88,7 @@ impl<'a> Value<'a> {
     }
 }
 
-pub(self) fn field_wire_type(field: &Field) -> u32 {
+fn field_wire_type(field: &Field) -> u32 {
     if field.repeated {
         WIRE_LEN_DELIM
     } else {
@@ -70,31 +114,51 @@ pub(self) fn field_wire_type(field: &Field) -> u32 {
     }
 }
 
-fn trans_default_value(buf: &mut Vec<u8>, field: &Field) {
+fn trans_default_value(buf: &mut Vec<u8>, field: &Field, opts: &ProtoToJsonOptions) {
     if field.repeated {
-        buf.extend_from_slice(b"null")
+        buf.extend_from_slice(b"[]")
     } else {
         match field.kind {
             Kind::Double
             | Kind::Float
             | Kind::Int32
-            | Kind::Int64
             | Kind::Uint32
-            | Kind::Uint64
             | Kind::Sint32
-            | Kind::Sint64
             | Kind::Fixed32
-            | Kind::Fixed64
-            | Kind::Sfixed32
-            | Kind::Sfixed64 => buf.push(b'0'),
+            | Kind::Sfixed32 => buf.push(b'0'),
+            Kind::Int64 | Kind::Uint64 | Kind::Sint64 | Kind::Fixed64 | Kind::Sfixed64 => {
+                if opts.quote_int64 {
+                    buf.extend_from_slice(b"\"0\"")
+                } else {
+                    buf.push(b'0')
+                }
+            }
             Kind::Bool => buf.extend_from_slice(b"false"),
             Kind::String | Kind::Bytes => buf.extend_from_slice(b"\"\""),
-            Kind::Map(_) | Kind::Message(_) => buf.extend_from_slice(b"null"),
+            Kind::Map(_) => buf.extend_from_slice(b"{}"),
+            Kind::Message(_) => buf.extend_from_slice(b"null"),
         }
     }
 }
 
-fn trans_map_kv(buf: &mut Vec<u8>, dec: &mut Decoder, entry: &Message) -> Result<()> {
+fn trans_unpopulated(buf: &mut Vec<u8>, field: &Field, more: &mut bool, opts: &ProtoToJsonOptions) {
+    if *more {
+        buf.push(b',');
+    } else {
+        *more = true;
+    }
+    buf.push(b'"');
+    buf.extend_from_slice(field.name.as_bytes());
+    buf.extend_from_slice(b"\":");
+    trans_default_value(buf, field, opts);
+}
+
+fn trans_map_kv(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    entry: &Message,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
     assert_eq!(entry.get_fields().len(), 2);
     let k_field = &entry.get_fields()[0];
     if !matches!(k_field.kind, Kind::String) {
@@ -133,20 +197,26 @@ fn trans_map_kv(buf: &mut Vec<u8>, dec: &mut Decoder, entry: &Message) -> Result
         }
     }
     if let Value::Bytes(k) = k_val {
-        trans_string(buf, k)?;
+        trans_string(buf, &check_utf8(k, &k_field.name, opts.utf8)?, opts.escape)?;
     } else {
         buf.extend_from_slice(b"\"\"");
     }
     buf.push(b':');
     if let Value::None = v_val {
-        trans_default_value(buf, v_field);
+        trans_default_value(buf, v_field, opts);
         Ok(())
     } else {
-        trans_field_value(buf, v_field, v_val)
+        trans_field_value(buf, v_field, v_val, opts)
     }
 }
 
-fn trans_repeated_impl<T, R>(buf: &mut Vec<u8>, dec: &mut Decoder, r: R) -> Result<()>
+fn trans_repeated_impl<T, R>(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    quote: bool,
+    opts: &ProtoToJsonOptions,
+    r: R,
+) -> Result<()>
 where
     T: Append,
     R: Fn(&mut Decoder) -> io::Result<T>,
@@ -159,44 +229,80 @@ where
         } else {
             buf.push(b',');
         };
-        r(dec).map(|v| v.append_into(buf)).map_err(Error::from)?;
+        let v = r(dec).map_err(Error::from)?;
+        if quote {
+            append_number(buf, v, true);
+        } else {
+            append_finite(buf, v, opts)?;
+        }
     }
     buf.push(b']');
     Ok(())
 }
 
-fn trans_repeated_field(buf: &mut Vec<u8>, dec: &mut Decoder, field: &Field) -> Result<()> {
+fn trans_repeated_field(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    field: &Field,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let q = opts.quote_int64;
     match field.kind {
-        Kind::Double => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<f64>()),
-        Kind::Float => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<f32>()),
-        Kind::Int32 => trans_repeated_impl(buf, dec, |dec| dec.read_varint().map(|v| v as i32)),
-        Kind::Int64 => trans_repeated_impl(buf, dec, |dec| dec.read_varint().map(|v| v as i64)),
-        Kind::Uint32 | Kind::Uint64 => trans_repeated_impl(buf, dec, |dec| dec.read_varint()),
-        Kind::Sint32 | Kind::Sint64 => trans_repeated_impl(buf, dec, |dec| dec.read_zigzag()),
-        Kind::Fixed32 => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<u32>()),
-        Kind::Fixed64 => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<u64>()),
-        Kind::Sfixed32 => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<i32>()),
-        Kind::Sfixed64 => trans_repeated_impl(buf, dec, |dec| dec.read_fixed::<i64>()),
-        Kind::Bool => trans_repeated_impl(buf, dec, |dec| dec.read_varint().map(|v| v != 0)),
+        Kind::Double => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_fixed::<f64>()),
+        Kind::Float => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_fixed::<f32>()),
+        Kind::Int32 => trans_repeated_impl(buf, dec, false, opts, |dec| {
+            dec.read_varint().map(|v| v as i32)
+        }),
+        Kind::Int64 => {
+            trans_repeated_impl(buf, dec, q, opts, |dec| dec.read_varint().map(|v| v as i64))
+        }
+        Kind::Uint32 => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_varint()),
+        Kind::Uint64 => trans_repeated_impl(buf, dec, q, opts, |dec| dec.read_varint()),
+        Kind::Sint32 => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_zigzag()),
+        Kind::Sint64 => trans_repeated_impl(buf, dec, q, opts, |dec| dec.read_zigzag()),
+        Kind::Fixed32 => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_fixed::<u32>()),
+        Kind::Fixed64 => trans_repeated_impl(buf, dec, q, opts, |dec| dec.read_fixed::<u64>()),
+        Kind::Sfixed32 => trans_repeated_impl(buf, dec, false, opts, |dec| dec.read_fixed::<i32>()),
+        Kind::Sfixed64 => trans_repeated_impl(buf, dec, q, opts, |dec| dec.read_fixed::<i64>()),
+        Kind::Bool => trans_repeated_impl(buf, dec, false, opts, |dec| {
+            dec.read_varint().map(|v| v != 0)
+        }),
 
         _ => Err(Error::Wrap("unexpected type".into())),
     }
 }
 
-fn trans_string(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+fn trans_string(buf: &mut Vec<u8>, data: &[u8], escape: json::Escape) -> Result<()> {
     buf.push(b'"');
-    json::escape_string(data, buf);
+    json::escape_string_with(data, buf, escape);
     buf.push(b'"');
     Ok(())
 }
 
-fn trans_bytes(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+fn trans_bytes(buf: &mut Vec<u8>, data: &[u8], encoding: BytesEncoding) -> Result<()> {
+    let engine = match encoding {
+        BytesEncoding::Standard => &BASE64_STANDARD,
+        BytesEncoding::StandardNoPad => &BASE64_STANDARD_NO_PAD,
+        BytesEncoding::UrlSafe => &BASE64_URL_SAFE,
+        BytesEncoding::UrlSafeNoPad => &BASE64_URL_SAFE_NO_PAD,
+        BytesEncoding::Hex => {
+            const HEX: &[u8; 16] = b"0123456789abcdef";
+            buf.reserve(data.len() * 2 + 2);
+            buf.push(b'"');
+            for &c in data {
+                buf.push(HEX[(c >> 4) as usize]);
+                buf.push(HEX[(c & 0xf) as usize]);
+            }
+            buf.push(b'"');
+            return Ok(());
+        }
+    };
     buf.push(b'"');
-    let enc_len = (data.len() + 2) / 3 * 4;
+    let enc_len = base64::encoded_len(data.len(), engine.config().encode_padding()).unwrap();
     buf.reserve(enc_len);
     let n = buf.len();
-    let z = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr().offset(n as isize), enc_len) };
-    let m = BASE64_STANDARD
+    let z = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr().add(n), enc_len) };
+    let m = engine
         .encode_slice(data, z)
         .map_err(|e| Error::Wrap(e.into()))?;
     assert_eq!(enc_len, m);
@@ -205,21 +311,138 @@ fn trans_bytes(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
-fn trans_field_value(buf: &mut Vec<u8>, field: &Field, v: Value) -> Result<()> {
-    if field.repeated {
-        trans_repeated_field(buf, &mut Decoder::new(v.into_bytes()), field)
+fn trans_embedded_message(
+    buf: &mut Vec<u8>,
+    data: &[u8],
+    msg: &Message,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let mut dec = Decoder::new(data);
+    if let Some(conv) = opts.converters.lookup(msg.get_name(), opts.google_types) {
+        if !opts.canonical {
+            return conv.to_json(buf, &mut dec, opts);
+        }
+        // converters write JSON of their own, which has to be brought into canonical form
+        let mut s = Vec::new();
+        conv.to_json(&mut s, &mut dec, opts)?;
+        return json::write_canonical(&s, buf).map_err(|e| Error::Wrap(e.into()));
+    }
+    match msg.get_name() {
+        wkt::TIMESTAMP => wkt::timestamp_to_json(buf, &mut dec),
+        wkt::DURATION => wkt::duration_to_json(buf, &mut dec),
+        wkt::ANY => trans_any(buf, &mut dec, opts),
+        wkt::STRUCT => wkt::struct_to_json(buf, &mut dec, opts),
+        wkt::VALUE => wkt::value_to_json(buf, &mut dec, opts),
+        wkt::LIST_VALUE => wkt::list_value_to_json(buf, &mut dec, opts),
+        wkt::FIELD_MASK => wkt::field_mask_to_json(buf, &mut dec, opts),
+        wkt::EMPTY => {
+            buf.extend_from_slice(b"{}");
+            Ok(())
+        }
+        name => match wkt::wrapper_field(name) {
+            Some(field) => trans_wrapper(buf, &mut dec, &field, opts),
+            None => trans_message(buf, &mut dec, msg, opts),
+        },
+    }
+}
+
+fn trans_wrapper(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    field: &Field,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let wire_type = field_wire_type(field);
+    let mut val = Value::None;
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        let v = match wire {
+            WIRE_VARINT => dec.read_varint().map(Value::U64).map_err(Error::from)?,
+            WIRE_32BIT => dec.read_32bit().map(Value::U32).map_err(Error::from)?,
+            WIRE_64BIT => dec.read_64bit().map(Value::U64).map_err(Error::from)?,
+            WIRE_LEN_DELIM => dec.read_data().map(Value::Bytes).map_err(Error::from)?,
+            _ => return Err(Error::InvalidWireType),
+        };
+        if tag == field.tag {
+            if wire != wire_type {
+                return Err(Error::InvalidWireType);
+            }
+            val = v;
+        }
+    }
+    if let Value::None = val {
+        trans_default_value(buf, field, opts);
+        Ok(())
+    } else {
+        trans_field_value(buf, field, val, opts)
+    }
+}
+
+// Regular messages are inlined next to "@type", WKTs with a JSON mapping and types with a
+// converter go under "value".
+fn trans_any(buf: &mut Vec<u8>, dec: &mut Decoder, opts: &ProtoToJsonOptions) -> Result<()> {
+    let (type_url, value) = wkt::read_any(dec)?;
+    if type_url.is_empty() && value.is_empty() {
+        buf.extend_from_slice(b"{}");
+        return Ok(());
+    }
+    let msg = wkt::resolve_any(opts.resolver.as_deref(), type_url)?;
+    buf.extend_from_slice(b"{\"@type\":");
+    trans_string(buf, type_url.as_bytes(), opts.escape)?;
+    if wkt::has_json_mapping(msg.get_name())
+        || opts
+            .converters
+            .lookup(msg.get_name(), opts.google_types)
+            .is_some()
+    {
+        buf.extend_from_slice(b",\"value\":");
+        trans_embedded_message(buf, value, &msg, opts)?;
+        buf.push(b'}');
+    } else {
+        let start = buf.len();
+        trans_message(buf, &mut Decoder::new(value), &msg, opts)?;
+        if buf.len() - start > 2 {
+            buf[start] = b',';
+        } else {
+            buf.truncate(start);
+            buf.push(b'}');
+        }
+    }
+    Ok(())
+}
+
+fn trans_field_value(
+    buf: &mut Vec<u8>,
+    field: &Field,
+    v: Value,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    if field.repeated && !matches!(field.kind, Kind::String | Kind::Bytes | Kind::Message(_)) {
+        trans_repeated_field(buf, &mut Decoder::new(v.into_bytes()), field, opts)
     } else {
         match field.kind {
-            Kind::Map(ref entry) => trans_map_kv(buf, &mut Decoder::new(v.into_bytes()), &entry),
-            Kind::String => trans_string(buf, v.into_bytes()),
-            Kind::Bytes => trans_bytes(buf, v.into_bytes()),
-            Kind::Message(ref msg) => trans_message(buf, &mut Decoder::new(v.into_bytes()), msg),
-            Kind::Double => write_primitive!(buf, f64::from_le_bytes(v.into_u64().to_le_bytes())),
-            Kind::Float => write_primitive!(buf, f32::from_le_bytes(v.into_u32().to_le_bytes())),
+            Kind::Map(ref entry) => {
+                trans_map_kv(buf, &mut Decoder::new(v.into_bytes()), entry, opts)
+            }
+            Kind::String => trans_string(
+                buf,
+                &check_utf8(v.into_bytes(), &field.name, opts.utf8)?,
+                opts.escape,
+            ),
+            Kind::Bytes => trans_bytes(buf, v.into_bytes(), opts.bytes_encoding),
+            Kind::Message(ref msg) => trans_embedded_message(buf, v.into_bytes(), msg, opts),
+            Kind::Double => {
+                append_finite(buf, f64::from_le_bytes(v.into_u64().to_le_bytes()), opts)
+            }
+            Kind::Float => append_finite(buf, f32::from_le_bytes(v.into_u32().to_le_bytes()), opts),
             Kind::Int32 => write_primitive!(buf, v.into_u64() as i32),
-            Kind::Int64 | Kind::Sfixed64 => write_primitive!(buf, v.into_u64() as i64),
-            Kind::Uint32 | Kind::Uint64 | Kind::Fixed64 => write_primitive!(buf, v.into_u64()),
-            Kind::Sint32 | Kind::Sint64 => write_primitive!(buf, unzigzag(v.into_u64())),
+            Kind::Int64 | Kind::Sfixed64 => {
+                write_primitive!(buf, v.into_u64() as i64, opts.quote_int64)
+            }
+            Kind::Uint32 => write_primitive!(buf, v.into_u64()),
+            Kind::Uint64 | Kind::Fixed64 => write_primitive!(buf, v.into_u64(), opts.quote_int64),
+            Kind::Sint32 => write_primitive!(buf, unzigzag(v.into_u64())),
+            Kind::Sint64 => write_primitive!(buf, unzigzag(v.into_u64()), opts.quote_int64),
             Kind::Fixed32 => write_primitive!(buf, v.into_u32()),
             Kind::Sfixed32 => write_primitive!(buf, v.into_u32() as i32),
             Kind::Bool => write_primitive!(buf, v.into_u64() != 0),
@@ -227,12 +450,52 @@ fn trans_field_value(buf: &mut Vec<u8>, field: &Field, v: Value) -> Result<()> {
     }
 }
 
-fn trans_message(buf: &mut Vec<u8>, dec: &mut Decoder, msg: &Message) -> Result<()> {
+fn scan_tags(data: &[u8]) -> Result<Vec<u32>> {
+    let mut dec = Decoder::new(data);
+    let mut tags = Vec::new();
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match wire {
+            WIRE_VARINT => dec.read_varint().map(drop),
+            WIRE_32BIT => dec.read_32bit().map(drop),
+            WIRE_64BIT => dec.read_64bit().map(drop),
+            WIRE_LEN_DELIM => dec.read_data().map(drop),
+            _ => return Err(Error::InvalidWireType),
+        }
+        .map_err(Error::from)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+fn trans_message(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    msg: &Message,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
     let mut cur_tag = 0u32;
     let mut cur_field = None;
     let mut more = false;
     let mut expect_wire = 0u32;
     let mut rep_close = 0u8;
+    // absent fields in tag order, drained as the wire moves past them
+    let mut unpopulated = Vec::new();
+    if opts.emit_unpopulated {
+        let present = scan_tags(dec.remaining())?;
+        unpopulated.extend(
+            msg.get_fields()
+                .iter()
+                .filter(|f| !present.contains(&f.tag)),
+        );
+        unpopulated.sort_by_key(|f| f.tag);
+    }
+    let mut next = 0usize;
+    // member and map entry offsets, for canonical output
+    let mut members = Vec::new();
+    let mut entries = Vec::new();
 
     buf.push(b'{');
     while !dec.eof() {
@@ -251,15 +514,26 @@ fn trans_message(buf: &mut Vec<u8>, dec: &mut Decoder, msg: &Message) -> Result<
                 _ => continue,
             };
             if rep_close != 0 {
+                sort_members(buf, &mut entries, opts)?;
                 buf.push(rep_close);
                 rep_close = 0;
                 more = true;
             }
+            while next < unpopulated.len() && unpopulated[next].tag < tag {
+                if opts.canonical {
+                    members.push(buf.len());
+                }
+                trans_unpopulated(buf, unpopulated[next], &mut more, opts);
+                next += 1;
+            }
 
             cur_tag = tag;
             cur_field = Some(field);
             expect_wire = field_wire_type(field);
 
+            if opts.canonical {
+                members.push(buf.len());
+            }
             if !more {
                 more = true;
             } else {
@@ -290,25 +564,108 @@ fn trans_message(buf: &mut Vec<u8>, dec: &mut Decoder, msg: &Message) -> Result<
         }
 
         if rep_close != 0 {
+            if opts.canonical && rep_close == b'}' {
+                entries.push(buf.len());
+            }
             if !more {
                 more = true;
             } else {
                 buf.push(b',');
             }
         }
-        trans_field_value(buf, cur_field.unwrap(), val)?;
+        trans_field_value(buf, cur_field.unwrap(), val, opts)?;
     }
 
     if rep_close != 0 {
+        sort_members(buf, &mut entries, opts)?;
         buf.push(rep_close);
+        more = true;
     }
+    for field in &unpopulated[next..] {
+        if opts.canonical {
+            members.push(buf.len());
+        }
+        trans_unpopulated(buf, field, &mut more, opts);
+    }
+    sort_members(buf, &mut members, opts)?;
     buf.push(b'}');
 
     Ok(())
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BytesEncoding {
+    #[default]
+    Standard,
+    StandardNoPad,
+    UrlSafe,
+    UrlSafeNoPad,
+    Hex,
+}
+
+#[derive(Clone, Default)]
+pub struct ProtoToJsonOptions {
+    pub allow_partial: bool,
+    pub resolver: Option<Rc<dyn TypeResolver>>,
+    pub google_types: bool,
+    pub converters: Converters,
+    pub quote_int64: bool,
+    pub reject_non_finite: bool,
+    pub bytes_encoding: BytesEncoding,
+    pub emit_unpopulated: bool,
+    pub utf8: Utf8Policy,
+    pub escape: json::Escape,
+    pub pretty: Option<json::Pretty>,
+    // RFC 8785 output. It implies `quote_int64`, as 64-bit integers can't be exact JSON
+    // numbers, and escapes strings the RFC 8785 way, so it can't be combined with `pretty`
+    // or an `escape` other than `Minimal`.
+    pub canonical: bool,
+}
+
+impl ProtoToJsonOptions {
+    // The canonical proto3 JSON mapping.
+    pub fn spec() -> Self {
+        Self {
+            quote_int64: true,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn trans_proto_to_json_with(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    msg: &Message,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let data = dec.remaining();
+    if opts.canonical {
+        if opts.pretty.is_some() || opts.escape != json::Escape::Minimal {
+            return Err(Error::Wrap(
+                "canonical output can't be combined with pretty or escape".into(),
+            ));
+        }
+        let opts = ProtoToJsonOptions {
+            quote_int64: true,
+            escape: json::Escape::Canonical,
+            ..opts.clone()
+        };
+        trans_message(buf, dec, msg, &opts)?;
+    } else if let Some(ref pretty) = opts.pretty {
+        let mut compact = Vec::new();
+        trans_message(&mut compact, dec, msg, opts)?;
+        json::write_pretty(&compact, buf, pretty).map_err(|e| Error::Wrap(e.into()))?;
+    } else {
+        trans_message(buf, dec, msg, opts)?;
+    }
+    if !opts.allow_partial {
+        check_initialized(data, msg)?;
+    }
+    Ok(())
+}
+
 pub fn trans_proto_to_json(buf: &mut Vec<u8>, dec: &mut Decoder, msg: &Message) -> Result<()> {
-    trans_message(buf, dec, msg)
+    trans_proto_to_json_with(buf, dec, msg, &ProtoToJsonOptions::default())
 }
 
 #[cfg(test)]
@@ -333,6 +690,19 @@ mod tests {
         test_trans_proto_to_json(&[], &get_msg_foo_type());
     }
 
+    #[test]
+    fn test_trans_proto_to_json_repeated_elements() {
+        let mut buf = Vec::new();
+        let data = [
+            50, 2, 102, 48, 50, 0, 58, 6, 8, 6, 18, 2, 115, 48, 58, 0, 42, 2, 3, 4,
+        ];
+        trans_proto_to_json(&mut buf, &mut Decoder::new(&data), &get_msg_foo_type()).unwrap();
+        assert_eq!(
+            ::std::str::from_utf8(&buf).unwrap(),
+            r#"{"f":["f0",""],"g":[{"a":6,"s":"s0"},{}],"e":[3,4]}"#
+        );
+    }
+
     #[test]
     fn test_trans_proto_to_json_case1() {
         test_trans_proto_to_json(
@@ -367,9 +737,359 @@ mod tests {
                     tag: 1,
                     kind: Kind::Bytes,
                     repeated: false,
+                    required: false,
                 }],
                 false,
             ),
         );
     }
+
+    fn get_msg_int64_type() -> Message {
+        let entry = Message::new(
+            "pbmsg.Int64.MEntry".to_string(),
+            vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Sint64, false),
+            ],
+            true,
+        );
+        Message::new(
+            "pbmsg.Int64".to_string(),
+            vec![
+                field("i", 1, Kind::Int64, false),
+                field("u", 2, Kind::Uint32, false),
+                field("f", 3, Kind::Fixed64, true),
+                field("m", 4, Kind::Map(Rc::new(entry)), false),
+            ],
+            true,
+        )
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_quote_int64() {
+        let msg = get_msg_int64_type();
+        let mut enc = Encoder::new();
+        enc.emit_varint(1, -9007199254740993i64 as u64);
+        enc.emit_varint(2, 7);
+        let mut packed = Encoder::new();
+        packed.write_slice(&u64::MAX.to_le_bytes());
+        packed.write_slice(&1u64.to_le_bytes());
+        enc.emit_len_delim(3, packed.as_bytes());
+        let mut entry = Encoder::new();
+        entry.emit_len_delim(1, b"a");
+        entry.emit_zigzag(2, -2);
+        enc.emit_len_delim(4, entry.as_bytes());
+        enc.emit_len_delim(4, b"\x0a\x01b");
+
+        let mut buf = Vec::new();
+        trans_proto_to_json(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg).unwrap();
+        assert_eq!(
+            ::std::str::from_utf8(&buf).unwrap(),
+            r#"{"i":-9007199254740993,"u":7,"f":[18446744073709551615,1],"m":{"a":-2,"b":0}}"#
+        );
+
+        let mut buf = Vec::new();
+        let opts = ProtoToJsonOptions::spec();
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts).unwrap();
+        assert_eq!(
+            ::std::str::from_utf8(&buf).unwrap(),
+            r#"{"i":"-9007199254740993","u":7,"f":["18446744073709551615","1"],"m":{"a":"-2","b":"0"}}"#
+        );
+
+        let mut z = Encoder::new();
+        let mut it = json::Iter::new(&buf);
+        crate::trans::trans_json_to_proto(&mut z, &mut it, &msg).unwrap();
+        assert_eq!(z.as_bytes(), enc.as_bytes());
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_non_finite() {
+        let msg = get_msg_floats_type();
+        let mut buf = Vec::new();
+        let data = get_floats_non_finite();
+        trans_proto_to_json(&mut buf, &mut Decoder::new(&data), &msg).unwrap();
+        assert_eq!(
+            ::std::str::from_utf8(&buf).unwrap(),
+            r#"{"d":"NaN","f":["Infinity",1.5,"-Infinity"],"m":{"a":"-Infinity"}}"#
+        );
+
+        let opts = ProtoToJsonOptions {
+            reject_non_finite: true,
+            ..Default::default()
+        };
+        for data in [&b"\x09\0\0\0\0\0\0\xf8\x7f"[..], b"\x12\x04\0\0\x80\xff"] {
+            let mut buf = Vec::new();
+            let err = trans_proto_to_json_with(&mut buf, &mut Decoder::new(data), &msg, &opts);
+            assert_eq!(
+                err.unwrap_err().to_string(),
+                "wrap: non-finite value is not allowed"
+            );
+        }
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_bytes_encoding() {
+        let msg = get_msg_blobs_type();
+        let data = get_blobs();
+        for (encoding, output) in [
+            (BytesEncoding::Standard, r#""+/8=""#),
+            (BytesEncoding::StandardNoPad, r#""+/8""#),
+            (BytesEncoding::UrlSafe, r#""-_8=""#),
+            (BytesEncoding::UrlSafeNoPad, r#""-_8""#),
+            (BytesEncoding::Hex, r#""fbff""#),
+        ] {
+            let opts = ProtoToJsonOptions {
+                bytes_encoding: encoding,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(&data), &msg, &opts).unwrap();
+            assert_eq!(
+                ::std::str::from_utf8(&buf).unwrap(),
+                format!(r#"{{"b":{0},"r":[{0},{0}],"m":{{"a":{0}}}}}"#, output)
+            );
+        }
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_emit_unpopulated() {
+        let trans = |s: &str, msg: &Message, opts: &ProtoToJsonOptions| {
+            let mut enc = Encoder::new();
+            let mut it = json::Iter::new(s.as_bytes());
+            crate::trans::trans_json_to_proto(&mut enc, &mut it, msg).unwrap();
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), msg, opts)
+                .unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let opts = ProtoToJsonOptions {
+            emit_unpopulated: true,
+            ..Default::default()
+        };
+        let msg = get_msg_foo_type();
+        for (input, output) in [
+            (
+                r#"{}"#,
+                r#"{"a":"","b":false,"c":0,"d":null,"e":[],"f":[],"g":[]}"#,
+            ),
+            (
+                r#"{"c":5,"f":["x"]}"#,
+                r#"{"a":"","b":false,"c":5,"d":null,"e":[],"f":["x"],"g":[]}"#,
+            ),
+            (
+                r#"{"d":{"a":2},"g":[{"a":1}]}"#,
+                r#"{"a":"","b":false,"c":0,"d":{"a":2,"b":""},"e":[],"f":[],"g":[{"a":1,"s":""}]}"#,
+            ),
+        ] {
+            assert_eq!(trans(input, &msg, &opts), output);
+        }
+
+        let opts = ProtoToJsonOptions {
+            emit_unpopulated: true,
+            ..ProtoToJsonOptions::spec()
+        };
+        assert_eq!(
+            trans(r#"{"u":1}"#, &get_msg_int64_type(), &opts),
+            r#"{"i":"0","u":1,"f":[],"m":{}}"#
+        );
+
+        let msg = get_msg_foo_type();
+        let mut enc = Encoder::new();
+        enc.emit_varint(3, 7);
+        enc.emit_len_delim(6, b"x");
+        enc.emit_len_delim(1, b"y");
+        let mut buf = Vec::new();
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            r#"{"b":false,"c":7,"d":null,"e":[],"f":["x"],"a":"y","g":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_utf8() {
+        let msg = get_msg_foo_type();
+        let mut enc = Encoder::new();
+        enc.emit_len_delim(1, b"\xffa");
+        enc.emit_len_delim(6, b"b\xc3");
+        let trans = |utf8| {
+            let opts = ProtoToJsonOptions {
+                utf8,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts)
+                .map(|_| buf)
+        };
+        assert_eq!(
+            trans(Utf8Policy::Reject).unwrap_err().to_string(),
+            "wrap: invalid utf-8 in field 'a': invalid utf-8 sequence of 1 bytes from index 0"
+        );
+        assert_eq!(
+            trans(Utf8Policy::Replace).unwrap(),
+            "{\"a\":\"\u{fffd}a\",\"f\":[\"b\u{fffd}\"]}".as_bytes()
+        );
+        assert_eq!(
+            trans(Utf8Policy::Pass).unwrap(),
+            b"{\"a\":\"\xffa\",\"f\":[\"b\xc3\"]}"
+        );
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_escape() {
+        let msg = get_msg_foo_type();
+        let mut enc = Encoder::new();
+        enc.emit_len_delim(1, "<é\x01>".as_bytes());
+        for (escape, output) in [
+            (json::Escape::Minimal, r#"{"a":"<é\u0001>"}"#),
+            (json::Escape::HtmlSafe, r#"{"a":"\u003cé\u0001\u003e"}"#),
+            (json::Escape::Ascii, r#"{"a":"<\u00e9\u0001>"}"#),
+        ] {
+            let opts = ProtoToJsonOptions {
+                escape,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts)
+                .unwrap();
+            assert_eq!(::std::str::from_utf8(&buf).unwrap(), output);
+        }
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_pretty() {
+        let msg = get_msg_int64_type();
+        let mut enc = Encoder::new();
+        enc.emit_varint(1, 3);
+        let mut packed = Encoder::new();
+        packed.write_slice(&1u64.to_le_bytes());
+        packed.write_slice(&2u64.to_le_bytes());
+        enc.emit_len_delim(3, packed.as_bytes());
+        enc.emit_len_delim(4, b"\x0a\x01a\x10\x03");
+        enc.emit_len_delim(4, b"\x0a\x01b");
+
+        let opts = ProtoToJsonOptions {
+            pretty: Some(json::Pretty::default()),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts).unwrap();
+        assert_eq!(
+            ::std::str::from_utf8(&buf).unwrap(),
+            "{\n  \"i\": 3,\n  \"f\": [\n    1,\n    2\n  ],\n  \"m\": {\n    \"a\": -2,\n    \"b\": 0\n  }\n}"
+        );
+        let mut z = Encoder::new();
+        crate::trans::trans_json_to_proto(&mut z, &mut json::Iter::new(&buf), &msg).unwrap();
+        assert_eq!(z.as_bytes(), enc.as_bytes());
+
+        let msg = get_msg_foo_type();
+        let s = r#"{"a":"a","d":{"a":2,"b":"b"},"e":[3],"g":[{"a":6,"s":"s0"},{"a":7,"s":""}]}"#;
+        let mut enc = Encoder::new();
+        crate::trans::trans_json_to_proto(&mut enc, &mut json::Iter::new(s.as_bytes()), &msg)
+            .unwrap();
+        let opts = ProtoToJsonOptions {
+            pretty: Some(json::Pretty {
+                indent: json::Indent::Tab,
+                space_after_colon: false,
+            }),
+            emit_unpopulated: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts).unwrap();
+        assert!(::std::str::from_utf8(&buf)
+            .unwrap()
+            .starts_with("{\n\t\"a\":\"a\",\n\t\"b\":false,\n\t\"c\":0,\n\t\"d\":{\n\t\t\"a\":2,"));
+        let mut z = Encoder::new();
+        crate::trans::trans_json_to_proto(&mut z, &mut json::Iter::new(&buf), &msg).unwrap();
+        assert_eq!(z.as_bytes(), enc.as_bytes());
+    }
+
+    #[test]
+    fn test_trans_proto_to_json_canonical() {
+        let msg = get_msg_int64_type();
+        let trans = |entries: &[&[u8]]| {
+            let mut enc = Encoder::new();
+            for e in entries {
+                enc.emit_len_delim(4, e);
+            }
+            enc.emit_varint(2, 7);
+            enc.emit_varint(1, 1 << 60);
+            let opts = ProtoToJsonOptions {
+                canonical: true,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, &opts)
+                .unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let a = trans(&[
+            b"\x0a\x01b\x10\x01",
+            b"\x0a\x02\xc3\xa9",
+            b"\x0a\x01a\x10\x03",
+        ]);
+        let b = trans(&[
+            b"\x0a\x01a\x10\x03",
+            b"\x0a\x01b\x10\x01",
+            b"\x0a\x02\xc3\xa9",
+        ]);
+        assert_eq!(a, b);
+        assert_eq!(
+            a,
+            r#"{"i":"1152921504606846976","m":{"a":"-2","b":"-1","é":"0"},"u":7}"#
+        );
+
+        let kind =
+            |name: &str| Kind::Message(Rc::new(Message::new(name.to_string(), vec![], false)));
+        let msg = Message::new(
+            "pbmsg.Canonical".to_string(),
+            vec![
+                field("s", 1, Kind::String, false),
+                field("d", 2, Kind::Double, false),
+                field("v", 3, kind("google.protobuf.Struct"), false),
+                field("m", 4, kind("google.type.Money"), false),
+            ],
+            false,
+        );
+        let s = r#"{"s":"a/b","d":1e21,"v":{"b":1.5,"a":null},"m":{"currencyCode":"USD","amount":"1"}}"#;
+        let mut enc = Encoder::new();
+        let jopts = crate::trans::JsonToProtoOptions {
+            google_types: true,
+            ..Default::default()
+        };
+        crate::trans::trans_json_to_proto_with(
+            &mut enc,
+            &mut json::Iter::new(s.as_bytes()),
+            &msg,
+            &jopts,
+        )
+        .unwrap();
+        let trans = |opts: &ProtoToJsonOptions| {
+            let mut buf = Vec::new();
+            trans_proto_to_json_with(&mut buf, &mut Decoder::new(enc.as_bytes()), &msg, opts)
+                .map(|_| String::from_utf8(buf).unwrap())
+        };
+        let opts = ProtoToJsonOptions {
+            canonical: true,
+            google_types: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            trans(&opts).unwrap(),
+            r#"{"d":1e+21,"m":{"amount":"1","currencyCode":"USD"},"s":"a/b","v":{"a":null,"b":1.5}}"#
+        );
+        for opts in [
+            ProtoToJsonOptions {
+                pretty: Some(json::Pretty::default()),
+                ..opts.clone()
+            },
+            ProtoToJsonOptions {
+                escape: json::Escape::Ascii,
+                ..opts.clone()
+            },
+        ] {
+            assert!(trans(&opts).is_err());
+        }
+    }
 }
@@ -0,0 +1,1147 @@
+use std::rc::Rc;
+
+use crate::json::{
+    self, escape_string_with, unescape_string_with, Escape, Iter, Surrogates, Token,
+};
+use crate::metadata::{Field, Kind, Message};
+use crate::proto::*;
+
+use super::append::Append;
+use super::*;
+
+// https://protobuf.dev/programming-guides/proto3/#json
+
+pub const TIMESTAMP: &str = "google.protobuf.Timestamp";
+pub const DURATION: &str = "google.protobuf.Duration";
+pub const ANY: &str = "google.protobuf.Any";
+pub const STRUCT: &str = "google.protobuf.Struct";
+pub const VALUE: &str = "google.protobuf.Value";
+pub const LIST_VALUE: &str = "google.protobuf.ListValue";
+pub const EMPTY: &str = "google.protobuf.Empty";
+pub const FIELD_MASK: &str = "google.protobuf.FieldMask";
+
+// 0001-01-01T00:00:00Z and 9999-12-31T23:59:59Z
+const MIN_TIMESTAMP_SECONDS: i64 = -62135596800;
+const MAX_TIMESTAMP_SECONDS: i64 = 253402300799;
+
+// About 10000 years
+const MAX_DURATION_SECONDS: i64 = 315576000000;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+pub fn has_json_mapping(name: &str) -> bool {
+    matches!(
+        name,
+        TIMESTAMP | DURATION | ANY | STRUCT | VALUE | LIST_VALUE | EMPTY | FIELD_MASK
+    ) || wrapper_field(name).is_some()
+}
+
+// The `value` field of the wrapper types in wrappers.proto.
+pub fn wrapper_field(name: &str) -> Option<Field> {
+    let kind = match name {
+        "google.protobuf.DoubleValue" => Kind::Double,
+        "google.protobuf.FloatValue" => Kind::Float,
+        "google.protobuf.Int64Value" => Kind::Int64,
+        "google.protobuf.UInt64Value" => Kind::Uint64,
+        "google.protobuf.Int32Value" => Kind::Int32,
+        "google.protobuf.UInt32Value" => Kind::Uint32,
+        "google.protobuf.BoolValue" => Kind::Bool,
+        "google.protobuf.StringValue" => Kind::String,
+        "google.protobuf.BytesValue" => Kind::Bytes,
+        _ => return None,
+    };
+    Some(Field {
+        name: "value".to_string(),
+        tag: 1,
+        kind,
+        repeated: false,
+        required: false,
+    })
+}
+
+pub fn invalid(what: &str, detail: impl fmt::Display) -> Error {
+    Error::Wrap(format!("invalid {}: {}", what, detail).into())
+}
+
+pub fn skip_field(dec: &mut Decoder, wire: u32) -> Result<()> {
+    match wire {
+        WIRE_VARINT => dec.read_varint().map(|_| ()).map_err(Error::from),
+        WIRE_32BIT => dec.read_32bit().map(|_| ()).map_err(Error::from),
+        WIRE_64BIT => dec.read_64bit().map(|_| ()).map_err(Error::from),
+        WIRE_LEN_DELIM => dec.read_data().map(|_| ()).map_err(Error::from),
+        _ => Err(Error::InvalidWireType),
+    }
+}
+
+fn read_seconds_nanos(dec: &mut Decoder) -> Result<(i64, i32)> {
+    let mut seconds = 0i64;
+    let mut nanos = 0i32;
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match (tag, wire) {
+            (1, WIRE_VARINT) => seconds = dec.read_varint().map_err(Error::from)? as i64,
+            (2, WIRE_VARINT) => nanos = dec.read_varint().map_err(Error::from)? as i32,
+            (1 | 2, _) => return Err(Error::InvalidWireType),
+            _ => skip_field(dec, wire)?,
+        }
+    }
+    Ok((seconds, nanos))
+}
+
+fn write_seconds_nanos(enc: &mut Encoder, seconds: i64, nanos: i32) {
+    if seconds != 0 {
+        enc.emit_varint(1, seconds as u64);
+    }
+    if nanos != 0 {
+        enc.emit_varint(2, nanos as i64 as u64);
+    }
+}
+
+pub fn unescape_token(what: &str, lead: Token, lone: Surrogates) -> Result<String> {
+    match lead {
+        Token::String(s) => {
+            let mut z = Vec::with_capacity(s.len() - 2);
+            unescape_string_with(&s[1..s.len() - 1], &mut z, lone).map_err(|e| invalid(what, e))?;
+            String::from_utf8(z).map_err(|e| invalid(what, e))
+        }
+        _ => Err(Error::TypeMismatch),
+    }
+}
+
+// Writes 0, 3, 6 or 9 fractional digits, whichever is the shortest exact form.
+pub fn append_nanos(buf: &mut Vec<u8>, nanos: u32) {
+    if nanos == 0 {
+        return;
+    }
+    let (v, width) = if nanos.is_multiple_of(1_000_000) {
+        (nanos / 1_000_000, 3)
+    } else if nanos.is_multiple_of(1_000) {
+        (nanos / 1_000, 6)
+    } else {
+        (nanos, 9)
+    };
+    buf.push(b'.');
+    append_padded(buf, v as u64, width);
+}
+
+pub fn append_padded(buf: &mut Vec<u8>, v: u64, width: usize) {
+    let mut b = itoa::Buffer::new();
+    let printed = b.format(v);
+    for _ in printed.len()..width {
+        buf.push(b'0');
+    }
+    buf.extend_from_slice(printed.as_bytes());
+}
+
+pub fn parse_digits(s: &[u8]) -> Option<u32> {
+    if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(s.iter().fold(0u32, |a, &c| a * 10 + (c - b'0') as u32))
+}
+
+// Parses the fraction after the '.' of "<n>.<frac>" into nanoseconds.
+pub fn parse_nanos(s: &[u8]) -> Option<u32> {
+    if s.len() > 9 {
+        return None;
+    }
+    parse_digits(s).map(|v| v * 10u32.pow(9 - s.len() as u32))
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + (m <= 2) as i64;
+    (y, m, d)
+}
+
+pub fn days_in_month(y: i64, m: u32) -> u32 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub fn timestamp_to_json(buf: &mut Vec<u8>, dec: &mut Decoder) -> Result<()> {
+    let (seconds, nanos) = read_seconds_nanos(dec)?;
+    if !(MIN_TIMESTAMP_SECONDS..=MAX_TIMESTAMP_SECONDS).contains(&seconds) {
+        return Err(invalid(
+            "timestamp",
+            format!("seconds out of range: {}", seconds),
+        ));
+    }
+    if !(0..NANOS_PER_SECOND as i32).contains(&nanos) {
+        return Err(invalid(
+            "timestamp",
+            format!("nanos out of range: {}", nanos),
+        ));
+    }
+    let (y, m, d) = civil_from_days(seconds.div_euclid(86400));
+    let secs = seconds.rem_euclid(86400);
+    buf.push(b'"');
+    append_padded(buf, y as u64, 4);
+    buf.push(b'-');
+    append_padded(buf, m as u64, 2);
+    buf.push(b'-');
+    append_padded(buf, d as u64, 2);
+    buf.push(b'T');
+    append_padded(buf, (secs / 3600) as u64, 2);
+    buf.push(b':');
+    append_padded(buf, (secs / 60 % 60) as u64, 2);
+    buf.push(b':');
+    append_padded(buf, (secs % 60) as u64, 2);
+    append_nanos(buf, nanos as u32);
+    buf.extend_from_slice(b"Z\"");
+    Ok(())
+}
+
+fn parse_timestamp(s: &str) -> Option<(i64, i32)> {
+    let b = s.as_bytes();
+    if b.len() < 20
+        || b[4] != b'-'
+        || b[7] != b'-'
+        || !matches!(b[10], b'T' | b't')
+        || b[13] != b':'
+        || b[16] != b':'
+    {
+        return None;
+    }
+    let y = parse_digits(&b[0..4])? as i64;
+    let mo = parse_digits(&b[5..7])?;
+    let d = parse_digits(&b[8..10])?;
+    let h = parse_digits(&b[11..13])?;
+    let mi = parse_digits(&b[14..16])?;
+    let sec = parse_digits(&b[17..19])?;
+    if y < 1 || !(1..=12).contains(&mo) || d < 1 || d > days_in_month(y, mo) {
+        return None;
+    }
+    if h > 23 || mi > 59 || sec > 59 {
+        return None;
+    }
+
+    let mut rest = &b[19..];
+    let mut nanos = 0;
+    if rest[0] == b'.' {
+        let n = rest[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+        nanos = parse_nanos(&rest[1..1 + n])?;
+        rest = &rest[1 + n..];
+    }
+    let offset = match rest {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), hh0, hh1, b':', mm0, mm1] => {
+            let oh = parse_digits(&[*hh0, *hh1])?;
+            let om = parse_digits(&[*mm0, *mm1])?;
+            if oh > 23 || om > 59 {
+                return None;
+            }
+            let v = (oh * 3600 + om * 60) as i64;
+            if *sign == b'-' {
+                -v
+            } else {
+                v
+            }
+        }
+        _ => return None,
+    };
+    let seconds = days_from_civil(y, mo, d) * 86400 + (h * 3600 + mi * 60 + sec) as i64 - offset;
+    Some((seconds, nanos as i32))
+}
+
+pub fn timestamp_from_json(
+    enc: &mut Encoder,
+    lead: Token,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    let s = unescape_token("timestamp", lead, opts.lone_surrogates)?;
+    let (seconds, nanos) =
+        parse_timestamp(&s).ok_or_else(|| invalid("timestamp", format!("'{}'", s)))?;
+    if !(MIN_TIMESTAMP_SECONDS..=MAX_TIMESTAMP_SECONDS).contains(&seconds) {
+        return Err(invalid("timestamp", format!("'{}' is out of range", s)));
+    }
+    write_seconds_nanos(enc, seconds, nanos);
+    Ok(())
+}
+
+pub fn duration_to_json(buf: &mut Vec<u8>, dec: &mut Decoder) -> Result<()> {
+    let (seconds, nanos) = read_seconds_nanos(dec)?;
+    if !(-MAX_DURATION_SECONDS..=MAX_DURATION_SECONDS).contains(&seconds) {
+        return Err(invalid(
+            "duration",
+            format!("seconds out of range: {}", seconds),
+        ));
+    }
+    if nanos.unsigned_abs() >= NANOS_PER_SECOND as u32 {
+        return Err(invalid(
+            "duration",
+            format!("nanos out of range: {}", nanos),
+        ));
+    }
+    if (seconds < 0 && nanos > 0) || (seconds > 0 && nanos < 0) {
+        return Err(invalid(
+            "duration",
+            format!("sign mismatch: seconds {}, nanos {}", seconds, nanos),
+        ));
+    }
+    buf.push(b'"');
+    if seconds < 0 || nanos < 0 {
+        buf.push(b'-');
+    }
+    append_padded(buf, seconds.unsigned_abs(), 1);
+    append_nanos(buf, nanos.unsigned_abs());
+    buf.extend_from_slice(b"s\"");
+    Ok(())
+}
+
+fn parse_duration(s: &str) -> Option<(i64, i32)> {
+    let b = s.as_bytes().strip_suffix(b"s")?;
+    let (neg, b) = match b.strip_prefix(b"-") {
+        Some(b) => (true, b),
+        None => (false, b),
+    };
+    let (int, frac) = match b.iter().position(|&c| c == b'.') {
+        Some(i) => (&b[..i], Some(&b[i + 1..])),
+        None => (b, None),
+    };
+    if int.is_empty() || int.len() > 12 || !int.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let seconds = int.iter().fold(0i64, |a, &c| a * 10 + (c - b'0') as i64);
+    let nanos = match frac {
+        Some(frac) => parse_nanos(frac)? as i32,
+        None => 0,
+    };
+    if neg {
+        Some((-seconds, -nanos))
+    } else {
+        Some((seconds, nanos))
+    }
+}
+
+pub fn duration_from_json(enc: &mut Encoder, lead: Token, opts: &JsonToProtoOptions) -> Result<()> {
+    let s = unescape_token("duration", lead, opts.lone_surrogates)?;
+    let (seconds, nanos) =
+        parse_duration(&s).ok_or_else(|| invalid("duration", format!("'{}'", s)))?;
+    if !(-MAX_DURATION_SECONDS..=MAX_DURATION_SECONDS).contains(&seconds) {
+        return Err(invalid("duration", format!("'{}' is out of range", s)));
+    }
+    write_seconds_nanos(enc, seconds, nanos);
+    Ok(())
+}
+
+pub fn read_any<'a>(dec: &mut Decoder<'a>) -> Result<(&'a str, &'a [u8])> {
+    let mut type_url = "";
+    let mut value: &[u8] = &[];
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match (tag, wire) {
+            (1, WIRE_LEN_DELIM) => {
+                type_url = std::str::from_utf8(dec.read_data().map_err(Error::from)?)
+                    .map_err(|e| invalid("type url", e))?
+            }
+            (2, WIRE_LEN_DELIM) => value = dec.read_data().map_err(Error::from)?,
+            (1 | 2, _) => return Err(Error::InvalidWireType),
+            _ => skip_field(dec, wire)?,
+        }
+    }
+    Ok((type_url, value))
+}
+
+pub fn resolve_any(resolver: Option<&dyn TypeResolver>, type_url: &str) -> Result<Rc<Message>> {
+    resolver
+        .and_then(|r| r.resolve(type_url))
+        .ok_or_else(|| Error::Wrap(format!("cannot resolve type url '{}'", type_url).into()))
+}
+
+enum ValueKind<'a> {
+    Null,
+    Number(f64),
+    String(&'a [u8]),
+    Bool(bool),
+    Struct(&'a [u8]),
+    List(&'a [u8]),
+}
+
+pub fn append_string(buf: &mut Vec<u8>, s: &[u8], escape: Escape) {
+    buf.push(b'"');
+    escape_string_with(s, buf, escape);
+    buf.push(b'"');
+}
+
+pub fn value_to_json(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let mut kind = None;
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        kind = Some(
+            match (tag, wire) {
+                (1, WIRE_VARINT) => dec.read_varint().map(|_| ValueKind::Null),
+                (2, WIRE_64BIT) => dec
+                    .read_64bit()
+                    .map(|v| ValueKind::Number(f64::from_bits(v))),
+                (3, WIRE_LEN_DELIM) => dec.read_data().map(ValueKind::String),
+                (4, WIRE_VARINT) => dec.read_varint().map(|v| ValueKind::Bool(v != 0)),
+                (5, WIRE_LEN_DELIM) => dec.read_data().map(ValueKind::Struct),
+                (6, WIRE_LEN_DELIM) => dec.read_data().map(ValueKind::List),
+                (1..=6, _) => return Err(Error::InvalidWireType),
+                _ => {
+                    skip_field(dec, wire)?;
+                    continue;
+                }
+            }
+            .map_err(Error::from)?,
+        );
+    }
+    match kind {
+        Some(ValueKind::Null) => buf.extend_from_slice(b"null"),
+        Some(ValueKind::Number(v)) => {
+            if !v.is_finite() {
+                return Err(invalid("value", format!("number {} is not finite", v)));
+            }
+            if opts.canonical {
+                let mut s = Vec::new();
+                v.append_into(&mut s);
+                json::write_canonical_number(&s, buf).map_err(|e| invalid("value", e))?;
+            } else {
+                v.append_into(buf)
+            }
+        }
+        Some(ValueKind::String(s)) => {
+            append_string(buf, &check_utf8(s, "string_value", opts.utf8)?, opts.escape)
+        }
+        Some(ValueKind::Bool(v)) => v.append_into(buf),
+        Some(ValueKind::Struct(data)) => struct_to_json(buf, &mut Decoder::new(data), opts)?,
+        Some(ValueKind::List(data)) => list_value_to_json(buf, &mut Decoder::new(data), opts)?,
+        None => return Err(invalid("value", "no kind is set")),
+    }
+    Ok(())
+}
+
+pub fn struct_to_json(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    buf.push(b'{');
+    let mut more = false;
+    let mut members = Vec::new();
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match (tag, wire) {
+            (1, WIRE_LEN_DELIM) => {
+                let mut entry = Decoder::new(dec.read_data().map_err(Error::from)?);
+                let mut key: &[u8] = &[];
+                let mut value: &[u8] = &[];
+                while !entry.eof() {
+                    let (tag, wire) = entry.read_key().map_err(Error::from)?;
+                    match (tag, wire) {
+                        (1, WIRE_LEN_DELIM) => key = entry.read_data().map_err(Error::from)?,
+                        (2, WIRE_LEN_DELIM) => value = entry.read_data().map_err(Error::from)?,
+                        (1 | 2, _) => return Err(Error::InvalidWireType),
+                        _ => skip_field(&mut entry, wire)?,
+                    }
+                }
+                if opts.canonical {
+                    members.push(buf.len());
+                }
+                if more {
+                    buf.push(b',');
+                }
+                more = true;
+                append_string(buf, &check_utf8(key, "key", opts.utf8)?, opts.escape);
+                buf.push(b':');
+                value_to_json(buf, &mut Decoder::new(value), opts)?;
+            }
+            (1, _) => return Err(Error::InvalidWireType),
+            _ => skip_field(dec, wire)?,
+        }
+    }
+    if opts.canonical {
+        json::sort_members(buf, &members).map_err(|e| invalid("struct", e))?;
+    }
+    buf.push(b'}');
+    Ok(())
+}
+
+pub fn list_value_to_json(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    buf.push(b'[');
+    let mut more = false;
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match (tag, wire) {
+            (1, WIRE_LEN_DELIM) => {
+                if more {
+                    buf.push(b',');
+                }
+                more = true;
+                value_to_json(
+                    buf,
+                    &mut Decoder::new(dec.read_data().map_err(Error::from)?),
+                    opts,
+                )?;
+            }
+            (1, _) => return Err(Error::InvalidWireType),
+            _ => skip_field(dec, wire)?,
+        }
+    }
+    buf.push(b']');
+    Ok(())
+}
+
+pub fn value_from_json(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    lead: Token,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    match lead {
+        Token::Null => enc.emit_varint(1, 0),
+        Token::Number(s) => {
+            let v = std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| invalid("number", String::from_utf8_lossy(s)))?;
+            enc.emit_u64(2, v.to_bits());
+        }
+        Token::String(_) => {
+            let s = unescape_token("string", lead, opts.lone_surrogates)?;
+            enc.emit_len_delim(3, s.as_bytes());
+        }
+        Token::True | Token::False => enc.emit_varint(4, matches!(lead, Token::True) as u64),
+        Token::Object => {
+            let mut z = Encoder::new();
+            struct_from_json(&mut z, it, lead, opts)?;
+            enc.emit_len_delim(5, z.as_bytes());
+        }
+        Token::Array => {
+            let mut z = Encoder::new();
+            list_value_from_json(&mut z, it, lead, opts)?;
+            enc.emit_len_delim(6, z.as_bytes());
+        }
+        _ => return Err(Error::UnexpectedToken),
+    }
+    Ok(())
+}
+
+pub fn struct_from_json(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    lead: Token,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    if !matches!(lead, Token::Object) {
+        return Err(Error::TypeMismatch);
+    }
+    let mut entry = Encoder::new();
+    let mut value = Encoder::new();
+    let mut key: Option<String> = None;
+    while let Some(tok) = it.next() {
+        match tok {
+            Token::ObjectClose if key.is_none() => return Ok(()),
+            Token::Comma | Token::Colon => continue,
+            _ => {
+                if let Some(k) = key.take() {
+                    value.clear();
+                    value_from_json(&mut value, it, tok, opts)?;
+                    entry.clear();
+                    entry.emit_len_delim(1, k.as_bytes());
+                    entry.emit_len_delim(2, value.as_bytes());
+                    enc.emit_len_delim(1, entry.as_bytes());
+                } else if let Token::String(_) = tok {
+                    key = Some(unescape_token("key", tok, opts.lone_surrogates)?);
+                } else {
+                    return Err(Error::UnexpectedToken);
+                }
+            }
+        }
+    }
+    Err(Error::UnexpectedEof)
+}
+
+pub fn list_value_from_json(
+    enc: &mut Encoder,
+    it: &mut Iter,
+    lead: Token,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    if !matches!(lead, Token::Array) {
+        return Err(Error::TypeMismatch);
+    }
+    let mut value = Encoder::new();
+    while let Some(tok) = it.next() {
+        match tok {
+            Token::Comma => continue,
+            Token::ArrayClose => return Ok(()),
+            _ => {
+                value.clear();
+                value_from_json(&mut value, it, tok, opts)?;
+                enc.emit_len_delim(1, value.as_bytes());
+            }
+        }
+    }
+    Err(Error::UnexpectedEof)
+}
+
+fn snake_to_camel(s: &str) -> String {
+    let mut z = String::with_capacity(s.len());
+    let mut upper = false;
+    for c in s.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            z.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            z.push(c);
+        }
+    }
+    z
+}
+
+fn camel_to_snake(s: &str) -> String {
+    let mut z = String::with_capacity(s.len() + 4);
+    for c in s.chars() {
+        if c.is_ascii_uppercase() {
+            z.push('_');
+            z.push(c.to_ascii_lowercase());
+        } else {
+            z.push(c);
+        }
+    }
+    z
+}
+
+pub fn field_mask_to_json(
+    buf: &mut Vec<u8>,
+    dec: &mut Decoder,
+    opts: &ProtoToJsonOptions,
+) -> Result<()> {
+    let mut z = String::new();
+    while !dec.eof() {
+        let (tag, wire) = dec.read_key().map_err(Error::from)?;
+        match (tag, wire) {
+            (1, WIRE_LEN_DELIM) => {
+                let path = check_utf8(dec.read_data().map_err(Error::from)?, "paths", opts.utf8)?;
+                // camelCase conversion needs text, even when invalid utf-8 is passed through
+                let path = std::str::from_utf8(&path).map_err(|e| invalid("field mask", e))?;
+                let camel = snake_to_camel(path);
+                if camel_to_snake(&camel) != path {
+                    return Err(invalid(
+                        "field mask",
+                        format!("path '{}' can't be converted to camelCase", path),
+                    ));
+                }
+                if !z.is_empty() {
+                    z.push(',');
+                }
+                z.push_str(&camel);
+            }
+            (1, _) => return Err(Error::InvalidWireType),
+            _ => skip_field(dec, wire)?,
+        }
+    }
+    append_string(buf, z.as_bytes(), opts.escape);
+    Ok(())
+}
+
+pub fn field_mask_from_json(
+    enc: &mut Encoder,
+    lead: Token,
+    opts: &JsonToProtoOptions,
+) -> Result<()> {
+    let s = unescape_token("field mask", lead, opts.lone_surrogates)?;
+    if s.is_empty() {
+        return Ok(());
+    }
+    for path in s.split(',') {
+        let snake = camel_to_snake(path);
+        if path.is_empty() || snake_to_camel(&snake) != path {
+            return Err(invalid(
+                "field mask",
+                format!("path '{}' can't be converted to snake_case", path),
+            ));
+        }
+        enc.emit_len_delim(1, snake.as_bytes());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::metadata::*;
+    use crate::trans::tests::*;
+    use crate::trans::{JsonToProtoOptions, ProtoToJsonOptions};
+
+    use super::*;
+
+    fn timestamp_type() -> Kind {
+        message_type(
+            TIMESTAMP,
+            vec![
+                field("seconds", 1, Kind::Int64, false),
+                field("nanos", 2, Kind::Int32, false),
+            ],
+        )
+    }
+
+    fn duration_type() -> Kind {
+        message_type(
+            DURATION,
+            vec![
+                field("seconds", 1, Kind::Int64, false),
+                field("nanos", 2, Kind::Int32, false),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let msg = holder(timestamp_type());
+        assert_eq!(
+            round_trip(r#"{"v":"1970-01-01T00:00:00Z"}"#, &msg).unwrap(),
+            r#"{"v":"1970-01-01T00:00:00Z"}"#
+        );
+        assert_eq!(
+            json_to_proto(r#"{"v":"1970-01-01T00:00:01.5Z"}"#, &msg).unwrap(),
+            vec![10, 8, 8, 1, 16, 128, 202, 181, 238, 1]
+        );
+        for (input, output) in [
+            ("2017-01-15T01:30:15.01Z", "2017-01-15T01:30:15.010Z"),
+            ("2017-01-15T01:30:15.000012Z", "2017-01-15T01:30:15.000012Z"),
+            (
+                "2017-01-15T01:30:15.123456789Z",
+                "2017-01-15T01:30:15.123456789Z",
+            ),
+            ("2017-01-15T01:30:15.000Z", "2017-01-15T01:30:15Z"),
+            ("2017-01-15t01:30:15z", "2017-01-15T01:30:15Z"),
+            ("2017-01-15T01:30:15+08:00", "2017-01-14T17:30:15Z"),
+            ("2017-01-14T20:00:00-05:30", "2017-01-15T01:30:00Z"),
+            ("0001-01-01T00:00:00Z", "0001-01-01T00:00:00Z"),
+            (
+                "9999-12-31T23:59:59.999999999Z",
+                "9999-12-31T23:59:59.999999999Z",
+            ),
+            ("1969-12-31T23:59:59.5Z", "1969-12-31T23:59:59.500Z"),
+            ("2000-02-29T00:00:00Z", "2000-02-29T00:00:00Z"),
+        ] {
+            assert_eq!(
+                round_trip(&format!(r#"{{"v":"{}"}}"#, input), &msg).unwrap(),
+                format!(r#"{{"v":"{}"}}"#, output),
+            );
+        }
+        assert_eq!(
+            round_trip(
+                r#"{"r":["2017-01-15T01:30:15Z","2017-01-15T01:30:16Z"],"m":{"k":"2017-01-15T01:30:15Z"}}"#,
+                &msg
+            ).unwrap(),
+            r#"{"r":["2017-01-15T01:30:15Z","2017-01-15T01:30:16Z"],"m":{"k":"2017-01-15T01:30:15Z"}}"#
+        );
+    }
+
+    #[test]
+    fn test_timestamp_invalid() {
+        let msg = holder(timestamp_type());
+        for input in [
+            r#""2017-01-15""#,
+            r#""2017-01-15T01:30:15""#,
+            r#""2017-01-15 01:30:15Z""#,
+            r#""2017-13-15T01:30:15Z""#,
+            r#""2017-02-29T01:30:15Z""#,
+            r#""2017-01-15T24:00:00Z""#,
+            r#""2017-01-15T01:30:15.Z""#,
+            r#""2017-01-15T01:30:15.1234567890Z""#,
+            r#""2017-01-15T01:30:15+0800""#,
+            r#""0000-12-31T23:59:59Z""#,
+            r#""0001-01-01T00:00:00+00:01""#,
+            r#"1484443815"#,
+            r#"{"seconds":1}"#,
+        ] {
+            let s = format!(r#"{{"v":{}}}"#, input);
+            assert!(json_to_proto(&s, &msg).is_err(), "{}", s);
+        }
+
+        // seconds: 253402300800
+        let data = [10, 7, 8, 128, 231, 205, 218, 176, 7];
+        assert!(proto_to_json(&data, &msg).is_err());
+        // nanos: -1
+        let data = [10, 11, 16, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1];
+        assert!(proto_to_json(&data, &msg).is_err());
+    }
+
+    #[test]
+    fn test_duration() {
+        let msg = holder(duration_type());
+        for (input, output) in [
+            ("0s", "0s"),
+            ("1s", "1s"),
+            ("1.5s", "1.500s"),
+            ("-1.5s", "-1.500s"),
+            ("-0.000001s", "-0.000001s"),
+            ("0.000000001s", "0.000000001s"),
+            ("3600.010s", "3600.010s"),
+            ("315576000000.999999999s", "315576000000.999999999s"),
+            ("-315576000000s", "-315576000000s"),
+        ] {
+            assert_eq!(
+                round_trip(&format!(r#"{{"v":"{}"}}"#, input), &msg).unwrap(),
+                format!(r#"{{"v":"{}"}}"#, output),
+            );
+        }
+        // seconds: -1, nanos: -500000000
+        assert_eq!(
+            json_to_proto(r#"{"v":"-1.5s"}"#, &msg).unwrap(),
+            vec![
+                10, 22, 8, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 16, 128, 182, 202, 145,
+                254, 255, 255, 255, 255, 1
+            ]
+        );
+        assert_eq!(
+            round_trip(r#"{"r":["1s","2s"],"m":{"k":"-3s"}}"#, &msg).unwrap(),
+            r#"{"r":["1s","2s"],"m":{"k":"-3s"}}"#
+        );
+    }
+
+    #[test]
+    fn test_duration_invalid() {
+        let msg = holder(duration_type());
+        for input in [
+            r#""1""#,
+            r#""s""#,
+            r#""1.s""#,
+            r#""+1s""#,
+            r#""--1s""#,
+            r#""1.5ms""#,
+            r#""1.0000000001s""#,
+            r#""315576000001s""#,
+            r#"1.5"#,
+        ] {
+            let s = format!(r#"{{"v":{}}}"#, input);
+            assert!(json_to_proto(&s, &msg).is_err(), "{}", s);
+        }
+
+        // seconds: 1, nanos: -1
+        let data = [
+            10, 13, 8, 1, 16, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1,
+        ];
+        assert!(proto_to_json(&data, &msg).is_err());
+        // nanos: 1000000000
+        let data = [10, 6, 16, 128, 148, 235, 220, 3];
+        assert!(proto_to_json(&data, &msg).is_err());
+    }
+
+    struct Registry(Vec<Rc<Message>>);
+
+    impl TypeResolver for Registry {
+        fn resolve(&self, type_url: &str) -> Option<Rc<Message>> {
+            let name = type_url.strip_prefix("type.googleapis.com/")?;
+            self.0.iter().find(|m| m.get_name() == name).cloned()
+        }
+    }
+
+    fn any_round_trip(s: &str, msg: &Message, resolver: Rc<dyn TypeResolver>) -> Result<String> {
+        let to_proto = JsonToProtoOptions {
+            resolver: Some(resolver.clone()),
+            ..Default::default()
+        };
+        let to_json = ProtoToJsonOptions {
+            resolver: Some(resolver),
+            ..Default::default()
+        };
+        round_trip_with(s, msg, &to_proto, &to_json)
+    }
+
+    #[test]
+    fn test_any() {
+        let any = message_type(
+            ANY,
+            vec![
+                field("type_url", 1, Kind::String, false),
+                field("value", 2, Kind::Bytes, false),
+            ],
+        );
+        let msg = holder(any.clone());
+        let registry: Rc<dyn TypeResolver> = Rc::new(Registry(
+            [get_msg_elem_type(), duration_type(), any]
+                .into_iter()
+                .map(|k| match k {
+                    Kind::Message(m) => m,
+                    _ => unreachable!(),
+                })
+                .collect(),
+        ));
+
+        for (input, output) in [
+            (r#"{"v":{}}"#, r#"{"v":{}}"#),
+            (
+                r#"{"v":{"@type":"type.googleapis.com/pbmsg.Elem","a":1,"s":"x"}}"#,
+                r#"{"v":{"@type":"type.googleapis.com\/pbmsg.Elem","a":1,"s":"x"}}"#,
+            ),
+            (
+                r#"{"v":{"s":"x","@type":"type.googleapis.com\/pbmsg.Elem","a":1}}"#,
+                r#"{"v":{"@type":"type.googleapis.com\/pbmsg.Elem","s":"x","a":1}}"#,
+            ),
+            (
+                r#"{"v":{"@type":"type.googleapis.com/pbmsg.Elem"}}"#,
+                r#"{"v":{"@type":"type.googleapis.com\/pbmsg.Elem"}}"#,
+            ),
+            (
+                r#"{"r":[{"value":"1.5s","@type":"type.googleapis.com/google.protobuf.Duration"}]}"#,
+                r#"{"r":[{"@type":"type.googleapis.com\/google.protobuf.Duration","value":"1.500s"}]}"#,
+            ),
+            (
+                r#"{"m":{"k":{"@type":"type.googleapis.com/google.protobuf.Any","value":{"@type":"type.googleapis.com/pbmsg.Elem","a":2}}}}"#,
+                r#"{"m":{"k":{"@type":"type.googleapis.com\/google.protobuf.Any","value":{"@type":"type.googleapis.com\/pbmsg.Elem","a":2}}}}"#,
+            ),
+        ] {
+            assert_eq!(
+                any_round_trip(input, &msg, registry.clone()).unwrap(),
+                output
+            );
+        }
+
+        for input in [
+            r#"{"v":{"a":1}}"#,
+            r#"{"v":{"@type":1}}"#,
+            r#"{"v":{"@type":"type.googleapis.com/pbmsg.Unknown"}}"#,
+            r#"{"v":{"@type":"type.googleapis.com/google.protobuf.Duration","seconds":1}}"#,
+            r#"{"v":"type.googleapis.com/pbmsg.Elem"}"#,
+        ] {
+            assert!(
+                any_round_trip(input, &msg, registry.clone()).is_err(),
+                "{}",
+                input
+            );
+        }
+
+        let err = any_round_trip(
+            r#"{"v":{"@type":"type.googleapis.com/pbmsg.Unknown"}}"#,
+            &msg,
+            registry,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "wrap: cannot resolve type url 'type.googleapis.com/pbmsg.Unknown'"
+        );
+        // Without a resolver, the payload can't be translated either way.
+        let data = [10, 4, 10, 2, 97, 47];
+        assert!(proto_to_json(&data, &msg).is_err());
+        assert!(proto_to_json(&[10, 0], &msg).is_ok());
+    }
+
+    #[test]
+    fn test_struct() {
+        let msg = holder(message_type(VALUE, vec![]));
+        assert_eq!(
+            json_to_proto(r#"{"v":null}"#, &msg).unwrap(),
+            vec![10, 2, 8, 0]
+        );
+        for (input, output) in [
+            (r#"{"v":null}"#, r#"{"v":null}"#),
+            (
+                r#"{"v":{"a":[1,"x\n",true,null,{"b":{}}],"c":[],"d":-2.5e3}}"#,
+                r#"{"v":{"a":[1.0,"x\n",true,null,{"b":{}}],"c":[],"d":-2500.0}}"#,
+            ),
+            (
+                r#"{"r":[false,null,"s",[]],"m":{"k":null}}"#,
+                r#"{"r":[false,null,"s",[]],"m":{"k":null}}"#,
+            ),
+        ] {
+            assert_eq!(round_trip(input, &msg).unwrap(), output);
+        }
+        assert!(json_to_proto(r#"{"v":1e400}"#, &msg).is_err());
+        assert!(json_to_proto(r#"{"v":[1,}"#, &msg).is_err());
+        // no kind set
+        assert!(proto_to_json(&[10, 0], &msg).is_err());
+        // number_value: NaN
+        assert!(proto_to_json(&[10, 9, 17, 0, 0, 0, 0, 0, 0, 248, 127], &msg).is_err());
+
+        let msg = holder(message_type(STRUCT, vec![]));
+        assert_eq!(
+            round_trip(r#"{"v":{"k":{"n":null}},"r":[{},{"a":"b"}]}"#, &msg).unwrap(),
+            r#"{"v":{"k":{"n":null}},"r":[{},{"a":"b"}]}"#
+        );
+        assert!(json_to_proto(r#"{"v":[]}"#, &msg).is_err());
+
+        let data = [10, 11, 10, 9, 10, 1, 255, 18, 4, 26, 2, 97, 254];
+        let trans = |utf8| {
+            let opts = ProtoToJsonOptions {
+                utf8,
+                ..Default::default()
+            };
+            proto_to_json_with(&data, &msg, &opts)
+        };
+        assert_eq!(
+            trans(Utf8Policy::Reject).unwrap_err().to_string(),
+            "wrap: invalid utf-8 in field 'key': invalid utf-8 sequence of 1 bytes from index 0"
+        );
+        assert_eq!(
+            trans(Utf8Policy::Replace).unwrap(),
+            "{\"v\":{\"\u{fffd}\":\"a\u{fffd}\"}}"
+        );
+
+        let trans = |s: &str, lone_surrogates| {
+            let opts = JsonToProtoOptions {
+                lone_surrogates,
+                ..Default::default()
+            };
+            json_to_proto_with(s, &msg, &opts)
+        };
+        let s = r#"{"v":{"\ud800":"a\udc00"}}"#;
+        assert!(trans(s, Surrogates::Reject).is_err());
+        assert_eq!(
+            trans(s, Surrogates::Replace).unwrap(),
+            trans(r#"{"v":{"\ufffd":"a\ufffd"}}"#, Surrogates::Reject).unwrap()
+        );
+
+        let msg = holder(message_type(LIST_VALUE, vec![]));
+        assert_eq!(
+            round_trip(r#"{"v":[1,[2,[]]],"m":{"k":[]}}"#, &msg).unwrap(),
+            r#"{"v":[1.0,[2.0,[]]],"m":{"k":[]}}"#
+        );
+        assert!(json_to_proto(r#"{"v":{}}"#, &msg).is_err());
+    }
+
+    #[test]
+    fn test_wrappers() {
+        let msg = holder(message_type("google.protobuf.Int32Value", vec![]));
+        assert_eq!(json_to_proto(r#"{"v":0}"#, &msg).unwrap(), vec![10, 0]);
+        assert_eq!(json_to_proto(r#"{"v":null}"#, &msg).unwrap(), vec![]);
+        assert_eq!(round_trip(r#"{"v":0}"#, &msg).unwrap(), r#"{"v":0}"#);
+        assert_eq!(
+            round_trip(r#"{"v":null,"r":[1,0,-2],"m":{"k":3}}"#, &msg).unwrap(),
+            r#"{"r":[1,0,-2],"m":{"k":3}}"#
+        );
+        assert!(json_to_proto(r#"{"v":{"value":1}}"#, &msg).is_err());
+        assert!(json_to_proto(r#"{"v":"x"}"#, &msg).is_err());
+        assert_eq!(round_trip(r#"{"v":"1"}"#, &msg).unwrap(), r#"{"v":1}"#);
+
+        for (name, input, output) in [
+            ("DoubleValue", "1.5", "1.5"),
+            ("FloatValue", "0", "0"),
+            ("Int64Value", "-9", "-9"),
+            (
+                "UInt64Value",
+                "18446744073709551615",
+                "18446744073709551615",
+            ),
+            ("UInt32Value", "4294967295", "4294967295"),
+            ("BoolValue", "false", "false"),
+            ("BoolValue", "true", "true"),
+            ("StringValue", r#""""#, r#""""#),
+            ("StringValue", r#""hi""#, r#""hi""#),
+            ("BytesValue", r#""aGk=""#, r#""aGk=""#),
+        ] {
+            let msg = holder(message_type(&format!("google.protobuf.{}", name), vec![]));
+            assert_eq!(
+                round_trip(&format!(r#"{{"v":{}}}"#, input), &msg).unwrap(),
+                format!(r#"{{"v":{}}}"#, output),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let msg = holder(message_type(EMPTY, vec![]));
+        assert_eq!(json_to_proto(r#"{"v":{}}"#, &msg).unwrap(), vec![10, 0]);
+        assert_eq!(
+            round_trip(r#"{"v":{},"r":[{},{}],"m":{"k":{}}}"#, &msg).unwrap(),
+            r#"{"v":{},"r":[{},{}],"m":{"k":{}}}"#
+        );
+        assert!(json_to_proto(r#"{"v":[]}"#, &msg).is_err());
+    }
+
+    #[test]
+    fn test_field_mask() {
+        let msg = holder(message_type(FIELD_MASK, vec![]));
+        assert_eq!(
+            json_to_proto(r#"{"v":"user.displayName,photo"}"#, &msg).unwrap(),
+            b"\x0a\x1a\x0a\x11user.display_name\x0a\x05photo".to_vec()
+        );
+        for (input, output) in [
+            (r#""""#, r#""""#),
+            (r#""a""#, r#""a""#),
+            (r#""user.displayName,photo""#, r#""user.displayName,photo""#),
+            (r#""fooBar.bazQux1""#, r#""fooBar.bazQux1""#),
+        ] {
+            assert_eq!(
+                round_trip(&format!(r#"{{"v":{}}}"#, input), &msg).unwrap(),
+                format!(r#"{{"v":{}}}"#, output),
+            );
+        }
+        assert_eq!(
+            round_trip(r#"{"r":["a,bC"],"m":{"k":"x.y"}}"#, &msg).unwrap(),
+            r#"{"r":["a,bC"],"m":{"k":"x.y"}}"#
+        );
+
+        for input in [
+            r#""foo_bar""#,
+            r#""a,,b""#,
+            r#""a,""#,
+            r#"["a"]"#,
+            r#"{"paths":["a"]}"#,
+        ] {
+            let s = format!(r#"{{"v":{}}}"#, input);
+            assert!(json_to_proto(&s, &msg).is_err(), "{}", s);
+        }
+        for path in ["foo__bar", "foo_1", "foo_", "fooBar"] {
+            let mut enc = Encoder::new();
+            enc.emit_len_delim(1, path.as_bytes());
+            let mut data = Encoder::new();
+            data.emit_len_delim(1, enc.as_bytes());
+            assert!(proto_to_json(data.as_bytes(), &msg).is_err(), "{}", path);
+        }
+
+        let mask = |path: &[u8], opts: &ProtoToJsonOptions| {
+            let mut enc = Encoder::new();
+            enc.emit_len_delim(1, path);
+            let mut data = Encoder::new();
+            data.emit_len_delim(1, enc.as_bytes());
+            proto_to_json_with(data.as_bytes(), &msg, opts)
+        };
+        let opts = ProtoToJsonOptions {
+            escape: Escape::Ascii,
+            ..Default::default()
+        };
+        assert_eq!(
+            mask("caf\u{e9}_name".as_bytes(), &opts).unwrap(),
+            r#"{"v":"caf\u00e9Name"}"#
+        );
+        let opts = ProtoToJsonOptions {
+            utf8: Utf8Policy::Replace,
+            ..Default::default()
+        };
+        assert_eq!(mask(b"a\xff", &opts).unwrap(), "{\"v\":\"a\u{fffd}\"}");
+        assert!(mask(b"a\xff", &ProtoToJsonOptions::default()).is_err());
+    }
+}